@@ -0,0 +1,33 @@
+#define_import_path slime_simulation::common
+
+struct Brush {
+    paint: vec4<f32>,
+    position: vec2<f32>,
+    isActive: u32,
+    tool: u32,
+    size: f32,
+    strength: f32,
+    deltaTime: f32,
+}
+
+fn isInBounds(location: vec2<i32>, size: vec2<i32>) -> bool {
+    return all(location >= vec2<i32>(0)) && all(location < size);
+}
+
+// Maps a texel location outside the canvas back onto it, clamped locations are left as is and read as empty
+fn getBoundaryLocation(location: vec2<i32>, size: vec2<i32>, boundaryMode: u32) -> vec2<i32> {
+    if (boundaryMode == 1u) {
+        return ((location % size) + size) % size;
+    } else if (boundaryMode == 2u) {
+        var mirrored = select(location, -location - 1, location < vec2<i32>(0));
+        mirrored = select(mirrored, 2 * size - mirrored - 1, mirrored >= size);
+        return clamp(mirrored, vec2<i32>(0), size - 1);
+    }
+
+    return location;
+}
+
+// Locations off the canvas are never obstacles
+fn isObstacle(obstacles: texture_2d<f32>, location: vec2<i32>) -> bool {
+    return isInBounds(location, vec2<i32>(textureDimensions(obstacles))) && textureLoad(obstacles, location, 0).r > 0.5;
+}
@@ -0,0 +1,148 @@
+#import slime_simulation::common
+
+struct Context {
+    pause: u32,
+    width: u32,
+    height: u32,
+    radius: u32,
+    kernel: u32,
+    decayMode: u32,
+    hasTrails: u32,
+    diffuseRate: f32,
+    decayRate: f32,
+    deltaTime: f32,
+    boundaryMode: u32,
+    brush: Brush,
+}
+
+@group(0) @binding(0)
+#ifdef TRAIL_FORMAT_RGBA8UNORM
+var texture: texture_storage_2d<rgba8unorm, read_write>;
+#else ifdef TRAIL_FORMAT_R32FLOAT
+var texture: texture_storage_2d<r32float, read_write>;
+#else
+var texture: texture_storage_2d<rgba16float, read_write>;
+#endif
+
+@group(0) @binding(1)
+#ifdef TRAIL_FORMAT_RGBA8UNORM
+var blurred: texture_storage_2d<rgba8unorm, read_write>;
+#else ifdef TRAIL_FORMAT_R32FLOAT
+var blurred: texture_storage_2d<r32float, read_write>;
+#else
+var blurred: texture_storage_2d<rgba16float, read_write>;
+#endif
+
+@group(0) @binding(2)
+var<uniform> context: Context;
+
+@group(0) @binding(3)
+var obstacles: texture_2d<f32>;
+
+fn getWeight(offset: i32) -> f32 {
+    if (context.kernel == 1u) {
+        // The radius covers two standard deviations of the gaussian
+        let sigma = max(f32(context.radius) / 2.0, 0.5);
+        return exp(-f32(offset * offset) / (2.0 * sigma * sigma));
+    }
+
+    return 1.0;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn diffuseHorizontal(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (context.pause == u32(1)) {
+        return;
+    }
+
+    let radius = i32(context.radius);
+    let size = vec2<i32>(i32(context.width), i32(context.height));
+    let location = vec2<i32>(id.xy);
+
+    var sum = textureLoad(texture, location) * getWeight(0);
+    var totalWeight = getWeight(0);
+
+    // Walks outwards from the texel and stops at the first obstacle, so trails never diffuse through walls
+    for (var direction = -1; direction <= 1; direction += 2) {
+        for (var x = 1; x <= radius; x++) {
+            let currentLocation = getBoundaryLocation(location + vec2<i32>(direction * x, 0), size, context.boundaryMode);
+
+            if (!isInBounds(currentLocation, size) || isObstacle(obstacles, currentLocation)) {
+                break;
+            }
+
+            let weight = getWeight(x);
+            sum += textureLoad(texture, currentLocation) * weight;
+            totalWeight += weight;
+        }
+    }
+
+    storageBarrier();
+    textureStore(blurred, location, sum / max(totalWeight, 0.0001));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn diffuseVertical(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (context.pause == u32(1)) {
+        return;
+    }
+
+    let radius = i32(context.radius);
+    let size = vec2<i32>(i32(context.width), i32(context.height));
+    let location = vec2<i32>(id.xy);
+
+    var sum = textureLoad(blurred, location) * getWeight(0);
+    var totalWeight = getWeight(0);
+
+    for (var direction = -1; direction <= 1; direction += 2) {
+        for (var y = 1; y <= radius; y++) {
+            let currentLocation = getBoundaryLocation(location + vec2<i32>(0, direction * y), size, context.boundaryMode);
+
+            if (!isInBounds(currentLocation, size) || isObstacle(obstacles, currentLocation)) {
+                break;
+            }
+
+            let weight = getWeight(y);
+            sum += textureLoad(blurred, currentLocation) * weight;
+            totalWeight += weight;
+        }
+    }
+
+    let value = textureLoad(texture, location);
+    let diffusedValue = mix(value, sum / max(totalWeight, 0.0001), min(context.diffuseRate * context.deltaTime, 1.0));
+
+    var decayedValue = max(vec4<f32>(0.0), diffusedValue - vec4<f32>(0.3));
+    if (context.hasTrails == u32(1)) {
+        if (context.decayMode == 1u) {
+            decayedValue = diffusedValue * exp(-context.decayRate * context.deltaTime * 5.0);
+        } else {
+            decayedValue = max(vec4<f32>(0.0), diffusedValue - vec4<f32>(context.decayRate * context.deltaTime * 5.0));
+        }
+    }
+
+    if (isObstacle(obstacles, location)) {
+        decayedValue = vec4<f32>(0.0);
+    }
+
+    storageBarrier();
+    textureStore(texture, location, decayedValue);
+}
+
+// Runs once per frame before the steps, even while paused. Painting blends the trail towards the brush colour
+// and erasing towards nothing, fading out towards the edge of the brush. Both trail maps hold the same values
+// between frames, so both are painted
+@compute @workgroup_size(8, 8, 1)
+fn paint(@builtin(global_invocation_id) id: vec3<u32>) {
+    let location = vec2<i32>(id.xy);
+    let distance = length(vec2<f32>(location) + 0.5 - context.brush.position);
+
+    if (distance >= context.brush.size || isObstacle(obstacles, location)) {
+        return;
+    }
+
+    let falloff = 1.0 - distance / context.brush.size;
+    let paintedValue = mix(textureLoad(texture, location), context.brush.paint, min(context.brush.strength * falloff * context.brush.deltaTime * 10.0, 1.0));
+
+    textureStore(texture, location, paintedValue);
+    textureStore(blurred, location, paintedValue);
+}
@@ -1,19 +1,83 @@
-struct Context {
+struct GradientStop {
     color: vec4<f32>,
+    position: f32,
+}
+
+struct Context {
+    colors: array<vec4<f32>, 4>,
+    numSpecies: u32,
+    depositMode: u32,
+    numStops: u32,
+    stops: array<GradientStop, 8>,
+    foodColor: vec4<f32>,
+    showFood: u32,
 }
 
 @group(0) @binding(0)
-var texture: texture_storage_2d<rgba8unorm, read_write>;
+#ifdef TRAIL_FORMAT_RGBA8UNORM
+var textureIn: texture_storage_2d<rgba8unorm, read>;
+#else ifdef TRAIL_FORMAT_R32FLOAT
+var textureIn: texture_storage_2d<r32float, read>;
+#else
+var textureIn: texture_storage_2d<rgba16float, read>;
+#endif
 
 @group(0) @binding(1)
+var textureOut: texture_storage_2d<rgba8unorm, write>;
+
+@group(0) @binding(2)
 var<uniform> context: Context;
 
+@group(0) @binding(3)
+var food: texture_2d<f32>;
+
+fn sampleGradient(t: f32) -> vec3<f32> {
+    var color = context.stops[0].color.rgb;
+    for (var i = 1u; i < context.numStops; i++) {
+        let previous = context.stops[i - 1u];
+        let next = context.stops[i];
+        color = mix(color, next.color.rgb, clamp((t - previous.position) / max(next.position - previous.position, 0.0001), 0.0, 1.0));
+    }
+
+    return color;
+}
+
 @compute @workgroup_size(8, 8, 1)
 fn recolor(@builtin(global_invocation_id) id: vec3<u32>) {
     let location = vec2<i32>(id.xy);
-    let value = textureLoad(texture, location);
-    let recolored_value = vec4<f32>(context.color[0], context.color[1], context.color[2], value[3]);
+    let value = textureLoad(textureIn, location);
+
+#ifdef TRAIL_SINGLE_CHANNEL
+    // Without species or colour channels the intensity is either mapped through the gradient or shown as greyscale
+    var color = vec3<f32>(value.r);
+    if (context.numStops > 0u) {
+        color = sampleGradient(min(value.r, 1.0));
+    }
+#else
+    var color = value.rgb;
+    if (context.numStops > 0u) {
+        var intensity = value.a;
+        if (context.depositMode == 0u) {
+            intensity = 0.0;
+            for (var i = 0u; i < context.numSpecies; i++) {
+                intensity += value[i];
+            }
+        }
+
+        color = sampleGradient(min(intensity, 1.0));
+    } else if (context.depositMode == 0u) {
+        color = vec3<f32>(0.0);
+        for (var i = 0u; i < context.numSpecies; i++) {
+            color += context.colors[i].rgb * context.colors[i].a * value[i];
+        }
+    }
+#endif
+
+    // Food sources are drawn on top so the network connecting them stays visible
+    if (context.showFood == 1u) {
+        color = mix(color, context.foodColor.rgb, min(textureLoad(food, location, 0).r, 1.0) * context.foodColor.a);
+    }
 
     storageBarrier();
-    textureStore(texture, location, recolored_value);
+    textureStore(textureOut, location, vec4<f32>(min(color, vec3<f32>(1.0)), 1.0));
 }
@@ -0,0 +1,27 @@
+struct Context {
+    scale: vec2<f32>,
+    count: u32,
+}
+
+struct Agent {
+    position: vec2<f32>,
+    angle: f32,
+    species: u32,
+}
+
+@group(0) @binding(0)
+var<storage, read_write> agents: array<Agent>;
+
+@group(0) @binding(1)
+var<uniform> context: Context;
+
+@compute @workgroup_size(16, 1, 1)
+fn rescale(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) numWorkgroups: vec3<u32>) {
+    let index = id.x + id.y * numWorkgroups.x * 16u;
+
+    if (index >= context.count) {
+        return;
+    }
+
+    agents[index].position *= context.scale;
+}
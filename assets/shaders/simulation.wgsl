@@ -1,26 +1,57 @@
+#import slime_simulation::common
+
 struct Context {
     pause: u32, // align(4)
     width: u32,
     height: u32,
-    speed: f32,
     deltaTime: f32,
-    time: f32,
+    tick: u32,
+    seed: u32,
+    depositMode: u32,
+    boundaryMode: u32,
+    brush: Brush,
+}
+
+struct Species {
+    speed: f32,
     senseAngleOffset: f32,
     senseDistance: f32,
     turnSpeed: f32,
     turnRandomness: f32,
+    sensorSize: u32,
+    sensorCount: u32,
+    sensorWeighting: u32,
+    color: vec4<f32>,
+}
+
+struct SpeciesContext {
+    species: array<Species, 4>,
+    numSpecies: u32,
 }
 
 struct Agent {
     position: vec2<f32>,
-    @align(8) angle: f32,
+    angle: f32,
+    species: u32,
 }
 
 @group(0) @binding(0)
+#ifdef TRAIL_FORMAT_RGBA8UNORM
 var textureIn: texture_storage_2d<rgba8unorm, read>;
+#else ifdef TRAIL_FORMAT_R32FLOAT
+var textureIn: texture_storage_2d<r32float, read>;
+#else
+var textureIn: texture_storage_2d<rgba16float, read>;
+#endif
 
 @group(0) @binding(1)
+#ifdef TRAIL_FORMAT_RGBA8UNORM
 var textureOut: texture_storage_2d<rgba8unorm, write>;
+#else ifdef TRAIL_FORMAT_R32FLOAT
+var textureOut: texture_storage_2d<r32float, write>;
+#else
+var textureOut: texture_storage_2d<rgba16float, write>;
+#endif
 
 @group(0) @binding(2)
 var<uniform> context: Context;
@@ -28,6 +59,15 @@ var<uniform> context: Context;
 @group(0) @binding(3)
 var<storage, read_write> agents: array<Agent>;
 
+@group(0) @binding(4)
+var<uniform> speciesContext: SpeciesContext;
+
+@group(0) @binding(5)
+var obstacles: texture_2d<f32>;
+
+@group(0) @binding(6)
+var food: texture_2d<f32>;
+
 fn hash(value: u32) -> u32 {
     var state = value;
     state = state ^ 2747636419u;
@@ -43,16 +83,29 @@ fn scaleTo01(value: u32) -> f32 {
     return f32(value) / 4294967295.0;
 }
 
-fn sense(id: u32, angleOffset: f32) -> f32 {
+fn hueToRgb(hue: f32) -> vec3<f32> {
+    return clamp(abs(fract(hue + vec3<f32>(0.0, 2.0 / 3.0, 1.0 / 3.0)) * 6.0 - 3.0) - 1.0, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn sense(id: u32, angleOffset: f32, senseDistance: f32, sensorSize: u32, weights: vec4<f32>) -> f32 {
     let angle = agents[id].angle + angleOffset;
     let direction = vec2<f32>(cos(angle), sin(angle));
-    let sensePosition = vec2<i32>(agents[id].position + direction * context.senseDistance);
+    let sensePosition = vec2<i32>(agents[id].position + direction * senseDistance);
+    let size = i32(sensorSize);
+    let canvasSize = vec2<i32>(i32(context.width), i32(context.height));
 
     var sum = 0.0;
 
-    for (var x = -2; x <= 2; x++) {
-        for (var y = -2; y <= 2; y++) {
-            sum += textureLoad(textureIn, sensePosition)[3];
+    for (var x = -size; x <= size; x++) {
+        for (var y = -size; y <= size; y++) {
+            let location = getBoundaryLocation(sensePosition + vec2<i32>(x, y), canvasSize, context.boundaryMode);
+
+            // Obstacles read like a full trail of another species, so agents steer away from them
+            if (isObstacle(obstacles, location)) {
+                sum -= 1.0;
+            } else if (isInBounds(location, canvasSize)) {
+                sum += dot(textureLoad(textureIn, location), weights) + textureLoad(food, location, 0).r;
+            }
         }
     }
 
@@ -60,44 +113,160 @@ fn sense(id: u32, angleOffset: f32) -> f32 {
 }
 
 @compute @workgroup_size(16, 1, 1)
-fn update(@builtin(global_invocation_id) id: vec3<u32>) {
-    if (context.pause == u32(1)) {
+fn update(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) numWorkgroups: vec3<u32>) {
+    // Large agent counts are dispatched over rows of workgroups, see get_agent_workgroup_size
+    let index = id.x + id.y * numWorkgroups.x * 16u;
+
+    if (context.pause == u32(1) || index >= arrayLength(&agents)) {
         return;
     }
 
-    var random = hash(u32(agents[id.x].position.x) * context.width + u32(agents[id.x].position.y) + hash(id.x + u32(context.time * 1000000.0)));
+    let stepSeed = hash(context.tick ^ hash(context.seed));
+    var random = hash(u32(agents[index].position.x) * context.width + u32(agents[index].position.y) + hash(index + stepSeed));
 
-    let senseLeft = sense(id.x, -context.senseAngleOffset);
-    let senseForward = sense(id.x, 0.0);
-    let senseRight = sense(id.x, context.senseAngleOffset);
+    let speciesIndex = agents[index].species % speciesContext.numSpecies;
+    let species = speciesContext.species[speciesIndex];
 
-    let turnSpeed = context.turnSpeed * 3.1415 * 2.0 * 0.01;
-    let turnRandomness = scaleTo01(random);
+    // Agents are attracted to the trail of their own species and repelled by all the others
+    var mask = vec4<f32>(0.0);
+    mask[speciesIndex] = 1.0;
+    var weights = mask * 2.0 - 1.0;
 
-    if (senseLeft > senseForward && senseLeft > senseRight) {
-        agents[id.x].angle -= turnSpeed;
-    } else if (senseRight > senseForward && senseRight > senseLeft) {
-        agents[id.x].angle += turnSpeed;
+#ifdef TRAIL_SINGLE_CHANNEL
+    // A single channel trail only holds intensity, so every agent follows and deposits that
+    weights = vec4<f32>(1.0, 0.0, 0.0, 0.0);
+#else
+    // Colour deposits can't tell the species apart, so agents follow the overall trail intensity instead
+    if (context.depositMode != 0u) {
+        weights = vec4<f32>(0.0, 0.0, 0.0, 1.0);
     }
+#endif
 
-    let direction = vec2<f32>(cos(agents[id.x].angle), sin(agents[id.x].angle));
-    var newPosition = agents[id.x].position + direction * context.speed * context.deltaTime * 50.0;
+    let turnSpeed = species.turnSpeed * 3.1415 * 2.0 * 0.01;
+    let randomStrength = scaleTo01(random);
+    let turnRandomness = clamp(species.turnRandomness, 0.0, 1.0);
 
-    if (newPosition.x < 0.0 || newPosition.x >= f32(context.width) || newPosition.y < 0.0 || newPosition.y >= f32(context.height)) {
+    // The sensors are spread evenly over the arc between the left and right sense angle
+    let sensorCount = max(species.sensorCount, 1u);
+
+    var forward = 0.0;
+    var hasForward = false;
+    var left = -1e30;
+    var right = -1e30;
+    var weightedAngle = 0.0;
+    var totalReading = 0.0;
+
+    for (var i = 0u; i < sensorCount; i++) {
+        var sensorAngle = 0.0;
+        if (sensorCount > 1u) {
+            sensorAngle = species.senseAngleOffset * (2.0 * f32(i) / f32(sensorCount - 1u) - 1.0);
+        }
+
+        let reading = sense(index, sensorAngle, species.senseDistance, species.sensorSize, weights);
+
+        if (sensorAngle < 0.0) {
+            left = max(left, reading);
+        } else if (sensorAngle > 0.0) {
+            right = max(right, reading);
+        } else {
+            forward = reading;
+            hasForward = true;
+        }
+
+        weightedAngle += max(reading, 0.0) * sensorAngle;
+        totalReading += max(reading, 0.0);
+    }
+
+    // Without any randomness agents always turn at the full turn speed,
+    // at full randomness the strength of every turn is random
+    let side = sign(right - left);
+
+    if (species.sensorWeighting == 1u) {
+        // Turns towards the average direction of the sensors weighted by what they read
+        if (totalReading > 0.0) {
+            let steer = clamp(weightedAngle / totalReading / max(species.senseAngleOffset, 0.0001), -1.0, 1.0);
+            agents[index].angle += turnSpeed * steer * mix(1.0, randomStrength, turnRandomness);
+        }
+    } else if (hasForward && forward > left && forward > right) {
+        // Keep going straight
+    } else if (hasForward && forward < left && forward < right) {
+        // Turns to a random side like the original rule, the lower half of the random value picks left and
+        // the fraction within each half is the random strength
+        let randomSide = select(1.0, -1.0, randomStrength < 0.5);
+        agents[index].angle += turnSpeed * randomSide * mix(1.0, fract(randomStrength * 2.0), turnRandomness);
+    } else {
+        agents[index].angle += turnSpeed * side * mix(1.0, randomStrength, turnRandomness);
+    }
+
+    // Agents under the attract brush turn towards its centre, the repel brush turns them away from it
+    if (context.brush.isActive == 1u && context.brush.tool >= 2u) {
+        let offset = context.brush.position - agents[index].position;
+        let distance = length(offset);
+
+        if (distance > 0.0 && distance < context.brush.size) {
+            var targetAngle = atan2(offset.y, offset.x);
+            if (context.brush.tool == 3u) {
+                targetAngle += 3.1415;
+            }
+
+            let angleDifference = targetAngle - agents[index].angle;
+            let falloff = 1.0 - distance / context.brush.size;
+            agents[index].angle += atan2(sin(angleDifference), cos(angleDifference)) * min(context.brush.strength * falloff * context.deltaTime * 10.0, 1.0);
+        }
+    }
+
+    let direction = vec2<f32>(cos(agents[index].angle), sin(agents[index].angle));
+    var newPosition = agents[index].position + direction * species.speed * context.deltaTime * 50.0;
+
+    let size = vec2<f32>(f32(context.width), f32(context.height));
+
+    if (context.boundaryMode == 1u) {
+        newPosition -= floor(newPosition / size) * size;
+    } else if (context.boundaryMode == 2u) {
+        // Specular reflection off the edges
+        if (newPosition.x < 0.0 || newPosition.x >= size.x) {
+            newPosition.x = select(2.0 * size.x - newPosition.x, -newPosition.x, newPosition.x < 0.0);
+            agents[index].angle = 3.1415 - agents[index].angle;
+        }
+        if (newPosition.y < 0.0 || newPosition.y >= size.y) {
+            newPosition.y = select(2.0 * size.y - newPosition.y, -newPosition.y, newPosition.y < 0.0);
+            agents[index].angle = -agents[index].angle;
+        }
+
+        newPosition = clamp(newPosition, vec2<f32>(0.0), size - 1.0);
+    } else if (newPosition.x < 0.0 || newPosition.x >= f32(context.width) || newPosition.y < 0.0 || newPosition.y >= f32(context.height)) {
         newPosition = vec2<f32>(
             min(f32(context.width) - 1.0, max(1.0, newPosition.x)),
             min(f32(context.height) - 1.0, max(1.0, newPosition.y)),
         );
 
         random = hash(random);
-        agents[id.x].angle = scaleTo01(random) * 3.1415 * 2.0;
+        agents[index].angle = scaleTo01(random) * 3.1415 * 2.0;
+    }
+
+    // Agents can't move onto an obstacle and pick a new direction instead, ones that started on one are free to leave
+    if (isObstacle(obstacles, vec2<i32>(newPosition)) && !isObstacle(obstacles, vec2<i32>(agents[index].position))) {
+        newPosition = agents[index].position;
+
+        random = hash(random);
+        agents[index].angle = scaleTo01(random) * 3.1415 * 2.0;
     }
 
-    agents[id.x].position = newPosition;
+    agents[index].position = newPosition;
 
-    let location = vec2<i32>(agents[id.x].position);
-    let color = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+    let location = vec2<i32>(agents[index].position);
+
+#ifdef TRAIL_SINGLE_CHANNEL
+    let deposit = vec4<f32>(1.0);
+#else
+    var deposit = mask;
+    if (context.depositMode == 1u) {
+        deposit = vec4<f32>(species.color.rgb, 1.0);
+    } else if (context.depositMode == 2u) {
+        deposit = vec4<f32>(hueToRgb(agents[index].angle / (3.1415 * 2.0)), 1.0);
+    }
+#endif
 
     storageBarrier();
-    textureStore(textureOut, location, color);
+    textureStore(textureOut, location, deposit);
 }
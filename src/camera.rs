@@ -0,0 +1,135 @@
+use bevy::input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+use bevy::render::render_resource::FilterMode;
+use bevy::render::texture::ImageSampler;
+use bevy::window::PrimaryWindow;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+
+use crate::AppConfig;
+use crate::pipeline::PipelineImages;
+use crate::plugin::{is_inspector_using_keyboard, is_inspector_using_pointer};
+
+const MIN_SCALE: f32 = 0.02;
+const MAX_SCALE: f32 = 4.0;
+const ZOOM_PER_LINE: f32 = 0.1;
+
+// Scroll to zoom, drag with the right or middle mouse button to pan and R to reset the view
+pub struct CameraControlsPlugin;
+
+impl Plugin for CameraControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system(zoom_camera)
+            .add_system(pan_camera)
+            .add_system(reset_camera)
+            .add_system(update_display_sampler.after(zoom_camera).after(reset_camera));
+    }
+}
+
+// Zooms towards the cursor, so the point under it stays in place
+fn zoom_camera(
+    mut scroll_events: EventReader<MouseWheel>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut egui_contexts: Query<&mut EguiContext, With<PrimaryWindow>>,
+    mut cameras: Query<(&Camera, &GlobalTransform, &mut Transform, &mut OrthographicProjection), With<Camera2d>>,
+) {
+    let scroll = scroll_events
+        .iter()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / 100.0,
+        })
+        .sum::<f32>();
+
+    if scroll == 0.0 || is_inspector_using_pointer(&mut egui_contexts) {
+        return;
+    }
+
+    let (Ok(window), Ok((camera, camera_global_transform, mut camera_transform, mut projection))) = (windows.get_single(), cameras.get_single_mut()) else {
+        return;
+    };
+
+    let scale = (projection.scale * (1.0 - ZOOM_PER_LINE).powf(scroll)).clamp(MIN_SCALE, MAX_SCALE);
+
+    if let Some(cursor) = window.cursor_position().and_then(|cursor| camera.viewport_to_world_2d(camera_global_transform, cursor)) {
+        let offset = cursor - camera_transform.translation.truncate();
+        let translation = cursor - offset * scale / projection.scale;
+
+        camera_transform.translation = translation.extend(camera_transform.translation.z);
+    }
+
+    projection.scale = scale;
+}
+
+fn pan_camera(
+    mouse_buttons: Res<Input<MouseButton>>,
+    mut motion_events: EventReader<MouseMotion>,
+    mut cameras: Query<(&mut Transform, &OrthographicProjection), With<Camera2d>>,
+) {
+    let delta = motion_events.iter().map(|event| event.delta).sum::<Vec2>();
+
+    if !mouse_buttons.any_pressed([MouseButton::Right, MouseButton::Middle]) || delta == Vec2::ZERO {
+        return;
+    }
+
+    let Ok((mut camera_transform, projection)) = cameras.get_single_mut() else {
+        return;
+    };
+
+    // Mouse motion is in window coordinates, which point down
+    camera_transform.translation.x -= delta.x * projection.scale;
+    camera_transform.translation.y += delta.y * projection.scale;
+}
+
+fn reset_camera(
+    keys: Res<Input<KeyCode>>,
+    mut egui_contexts: Query<&mut EguiContext, With<PrimaryWindow>>,
+    mut cameras: Query<(&mut Transform, &mut OrthographicProjection), With<Camera2d>>,
+) {
+    if !keys.just_pressed(KeyCode::R) || is_inspector_using_keyboard(&mut egui_contexts) {
+        return;
+    }
+
+    let Ok((mut camera_transform, mut projection)) = cameras.get_single_mut() else {
+        return;
+    };
+
+    camera_transform.translation.x = 0.0;
+    camera_transform.translation.y = 0.0;
+    projection.scale = 1.0;
+}
+
+// Texels are drawn as sharp squares once they cover more than one pixel of the window
+fn update_display_sampler(
+    app_config: Res<AppConfig>,
+    pipeline_images: Res<PipelineImages>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<&OrthographicProjection, With<Camera2d>>,
+    sprites: Query<&Sprite>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let (Ok(window), Ok(projection), Ok(sprite)) = (windows.get_single(), cameras.get_single(), sprites.get_single()) else {
+        return;
+    };
+
+    let Some(sprite_size) = sprite.custom_size else {
+        return;
+    };
+
+    let pixels_per_texel = sprite_size.x / app_config.texture.width as f32 / projection.scale * window.scale_factor() as f32;
+    let is_nearest = pixels_per_texel > 1.0;
+
+    let Some(display) = images.get(&pipeline_images.display) else {
+        return;
+    };
+
+    if matches!(&display.sampler_descriptor, ImageSampler::Descriptor(descriptor) if descriptor.mag_filter == FilterMode::Nearest) == is_nearest {
+        return;
+    }
+
+    images.get_mut(&pipeline_images.display).expect("display image to exist").sampler_descriptor = if is_nearest {
+        ImageSampler::nearest()
+    } else {
+        ImageSampler::Default
+    };
+}
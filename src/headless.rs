@@ -0,0 +1,114 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+
+use bevy::app::{AppExit, ScheduleRunnerSettings};
+use bevy::prelude::*;
+use bevy::render::{RenderApp, RenderSet};
+use bevy::render::renderer::RenderDevice;
+
+use crate::pipeline::readback::{DisplayReadback, prepare_readback};
+use crate::plugin::{FixedDeltaTime, PluginTime, SimulationSettings, TickLimit};
+
+pub struct HeadlessPlugin {
+    pub steps: u32,
+    pub delta_time: f32,
+    pub out: PathBuf,
+    // Set when the run failed, checked once the app has exited
+    pub failed: Arc<AtomicBool>,
+}
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        let run = HeadlessRun {
+            steps: self.steps,
+            out: self.out.clone(),
+            steps_done: Arc::new(AtomicU32::new(0)),
+            finished: Arc::new(AtomicBool::new(false)),
+            failed: self.failed.clone(),
+        };
+
+        app
+            .insert_resource(ScheduleRunnerSettings::run_loop(Duration::ZERO))
+            .insert_resource(FixedDeltaTime(self.delta_time))
+            .insert_resource(TickLimit(self.steps))
+            .insert_resource(run.clone())
+            .add_startup_system(unpause)
+            .add_system(exit_when_finished);
+
+        app
+            .sub_app_mut(RenderApp)
+            .insert_resource(run)
+            .add_system(count_steps.in_set(RenderSet::Prepare).after(prepare_readback))
+            .add_system(save_image.in_set(RenderSet::Cleanup));
+    }
+}
+
+#[derive(Clone, Resource)]
+struct HeadlessRun {
+    steps: u32,
+    out: PathBuf,
+    steps_done: Arc<AtomicU32>,
+    finished: Arc<AtomicBool>,
+    failed: Arc<AtomicBool>,
+}
+
+// Checked before starting, so a long run doesn't fail only once it is done
+pub fn validate_output(out: &Path) -> Result<(), String> {
+    if image::ImageFormat::from_path(out).ok() != Some(image::ImageFormat::Png) {
+        return Err(format!("Output image {} must be a .png file", out.display()));
+    }
+
+    match out.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() && !directory.is_dir() => {
+            Err(format!("Output directory {} does not exist", directory.display()))
+        },
+        _ => Ok(()),
+    }
+}
+
+fn unpause(mut settings: ResMut<SimulationSettings>) {
+    settings.pause = false;
+}
+
+fn exit_when_finished(run: Res<HeadlessRun>, mut exit: EventWriter<AppExit>) {
+    if run.finished.load(Ordering::Acquire) {
+        exit.send(AppExit);
+    }
+}
+
+// Steps are only taken once every pipeline has compiled, so the display is read back in the frame of the last one
+fn count_steps(run: Res<HeadlessRun>, time: Res<PluginTime>, mut display_readback: ResMut<DisplayReadback>) {
+    let steps_done = run.steps_done.fetch_add(time.steps, Ordering::AcqRel) + time.steps;
+
+    if steps_done >= run.steps && steps_done - time.steps < run.steps {
+        display_readback.is_requested = true;
+    }
+}
+
+fn save_image(run: Res<HeadlessRun>, display_readback: Res<DisplayReadback>, render_device: Res<RenderDevice>) {
+    if run.finished.load(Ordering::Acquire) || run.steps_done.load(Ordering::Acquire) < run.steps {
+        return;
+    }
+
+    let bytes = display_readback.readback.read_opaque(render_device.as_ref());
+
+    let saved = image::save_buffer(
+        &run.out,
+        &bytes,
+        display_readback.readback.width(),
+        display_readback.readback.height(),
+        image::ColorType::Rgba8,
+    );
+
+    match saved {
+        Ok(()) => info!("Saved {} after {} steps", run.out.display(), run.steps),
+        Err(err) => {
+            error!("Failed to save {}: {}", run.out.display(), err);
+            run.failed.store(true, Ordering::Release);
+        },
+    }
+
+    run.finished.store(true, Ordering::Release);
+}
@@ -1,73 +1,198 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
 
+use bevy::app::ScheduleRunnerPlugin;
 use bevy::asset::AssetPlugin;
 use bevy::DefaultPlugins;
 use bevy::prelude::*;
-use bevy::window::{PresentMode, WindowMode, WindowResized, WindowResolution};
+use bevy::render::extract_resource::ExtractResource;
+use bevy::render::renderer::RenderDevice;
+use bevy::window::{ExitCondition, PresentMode, PrimaryWindow, WindowMode, WindowResized, WindowResolution};
+use bevy::winit::WinitPlugin;
 use bevy_embedded_assets::EmbeddedAssetPlugin;
+use clap::Parser;
 use serde::{Deserialize, Serialize};
 
+use crate::camera::CameraControlsPlugin;
+use crate::headless::HeadlessPlugin;
 use crate::pipeline::PipelineImages;
-use crate::plugin::SlimeSimulationPlugin;
+use crate::pipeline::readback::ReadbackPlugin;
+use crate::pipeline::simulation::Agent;
+use crate::plugin::{MAX_GRADIENT_STOPS, MAX_SPECIES, SimulationSettings, SlimeSimulationPlugin};
+use crate::presets::{get_presets_directory, load_preset, PresetsPlugin};
+use crate::recording::RecordingPlugin;
 
+mod camera;
+mod headless;
 mod plugin;
+mod presets;
 mod pipeline;
+mod recording;
 
 const CONFIG_FILE_NAME: &str = "slime_simulation_config.toml";
+const CONFIG_POLL_INTERVAL: f32 = 0.5;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Run without a window and save the final frame to the output image
+    #[arg(long)]
+    headless: bool,
+    /// Number of simulation steps to run in headless mode
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+    steps: u32,
+    /// Delta time in seconds used for every step in headless mode
+    #[arg(long, default_value_t = 1.0 / 60.0, value_parser = parse_delta_time)]
+    delta_time: f32,
+    /// PNG file the final frame is written to in headless mode
+    #[arg(long, default_value = "frame.png")]
+    out: PathBuf,
+    /// Start recording right away as set up in the recording section of the config, in headless mode every step becomes a frame
+    #[arg(long)]
+    record: bool,
+    /// Config file to use, it is created with the defaults if it doesn't exist
+    #[arg(long, default_value = CONFIG_FILE_NAME)]
+    config: PathBuf,
+    /// Name of a preset in the presets directory or path of a preset file, replaces the simulation section of the config
+    #[arg(long)]
+    preset: Option<String>,
+    /// Overrides the canvas width
+    #[arg(long)]
+    texture_width: Option<u32>,
+    /// Overrides the canvas height
+    #[arg(long)]
+    texture_height: Option<u32>,
+    /// Overrides the number of agents
+    #[arg(long)]
+    agents: Option<u32>,
+    /// Overrides the seed, TOML can't hold integers above i64::MAX
+    #[arg(long, value_parser = clap::value_parser!(u64).range(..=i64::MAX as u64))]
+    seed: Option<u64>,
+    /// Overrides any value of the config, e.g. `--set pipeline.backend=cpu` or `--set simulation.decay_rate=0.3`
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_override)]
+    overrides: Vec<(String, toml::Value)>,
+    /// Print the effective config after applying the preset and overrides, then exit
+    #[arg(long)]
+    print_config: bool,
+}
 
 fn main() {
-    let config: AppConfig = match fs::read_to_string(CONFIG_FILE_NAME) {
-        Ok(contents) => {
-            toml::from_str(contents.as_str()).unwrap()
-        },
-        Err(..) => {
-            fs::write(CONFIG_FILE_NAME, toml::to_string(&AppConfig::default()).unwrap()).unwrap();
-            AppConfig::default()
+    let cli = Cli::parse();
+    let source = ConfigSource::new(&cli);
+
+    let config = match load_config(&source) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
         },
     };
 
+    if cli.print_config {
+        print!("{}", toml::to_string(&config).expect("config to serialize"));
+        return;
+    }
+
+    if cli.headless {
+        if let Err(err) = headless::validate_output(&cli.out) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
     let mut window_resolution = WindowResolution::new(
         config.window.width as f32,
         config.window.height as f32,
     );
     window_resolution.set_scale_factor_override(if config.window.override_scale_factor { Some(1.0) } else { None });
 
-    App::new()
+    let mut app = App::new();
+
+    app
         .insert_resource(ClearColor(Color::BLACK))
+        .insert_resource(config.simulation.clone())
         .insert_resource(config.clone())
-        .add_plugins(DefaultPlugins
-            .set(WindowPlugin {
-                primary_window: Some(Window {
-                    title: String::from("Slime Simulation"),
-                    resolution: window_resolution,
-                    resizable: config.window.resizable,
-                    mode: if config.window.fullscreen { WindowMode::Fullscreen } else { WindowMode::Windowed },
-                    present_mode: if config.window.vsync { PresentMode::AutoVsync } else { PresentMode::AutoNoVsync },
+        .insert_resource(source);
+
+    if cli.headless {
+        app
+            .add_plugins(DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: None,
+                    exit_condition: ExitCondition::DontExit,
+                    close_when_requested: false,
+                })
+                .build()
+                .disable::<WinitPlugin>()
+                .add_before::<AssetPlugin, _>(EmbeddedAssetPlugin),
+            )
+            .add_plugin(ScheduleRunnerPlugin);
+    } else {
+        app
+            .add_plugins(DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: Some(Window {
+                        title: String::from("Slime Simulation"),
+                        resolution: window_resolution,
+                        resizable: config.window.resizable,
+                        mode: if config.window.fullscreen { WindowMode::Fullscreen } else { WindowMode::Windowed },
+                        present_mode: if config.window.vsync { PresentMode::AutoVsync } else { PresentMode::AutoNoVsync },
+                        ..default()
+                    }),
                     ..default()
-                }),
-                ..default()
-            })
-            .build()
-            .add_before::<AssetPlugin, _>(EmbeddedAssetPlugin),
-        )
+                })
+                .build()
+                .add_before::<AssetPlugin, _>(EmbeddedAssetPlugin),
+            )
+            .add_plugin(CameraControlsPlugin)
+            .add_plugin(PresetsPlugin)
+            .add_system(reload_config);
+    }
+
+    app
         .add_plugin(SlimeSimulationPlugin)
+        .add_plugin(ReadbackPlugin)
+        .add_plugin(RecordingPlugin {
+            record: cli.record,
+        })
         .add_startup_system(setup.in_base_set(StartupSet::PostStartup))
         .add_system(on_window_resize)
-        .run();
+        .add_system(fit_sprite.after(on_window_resize).after(reload_config));
+
+    let headless_failed = Arc::new(AtomicBool::new(false));
+
+    if cli.headless {
+        app.add_plugin(HeadlessPlugin {
+            steps: cli.steps,
+            delta_time: cli.delta_time,
+            out: cli.out,
+            failed: headless_failed.clone(),
+        });
+    }
+
+    app.run();
+
+    if headless_failed.load(Ordering::Acquire) {
+        std::process::exit(1);
+    }
 }
 
 fn setup(mut commands: Commands, images: Res<PipelineImages>, config: Res<AppConfig>) {
     commands.spawn(SpriteBundle {
         sprite: Sprite {
-            custom_size: Some(Vec2::new(
-                config.window.width as f32,
-                config.window.height as f32,
+            custom_size: Some(get_sprite_size(
+                Vec2::new(config.window.width as f32, config.window.height as f32),
+                config.as_ref(),
             )),
             ..default()
         },
-        texture: images.0.last().cloned().unwrap(),
+        texture: images.display.clone(),
         ..default()
     });
 
@@ -77,23 +202,327 @@ fn setup(mut commands: Commands, images: Res<PipelineImages>, config: Res<AppCon
 fn on_window_resize(
     mut resize_events: EventReader<WindowResized>,
     mut query: Query<&mut Sprite>,
+    windows: Query<&Window>,
+    mut config: ResMut<AppConfig>,
 ) {
     for event in resize_events.iter() {
+        // Minimizing the window resizes it to nothing, which would throw away the canvas
+        if event.width == 0.0 || event.height == 0.0 {
+            continue;
+        }
+
+        if config.window.resizable && config.texture.follow_window {
+            if let Ok(window) = windows.get(event.window) {
+                config.texture.width = window.physical_width().max(1);
+                config.texture.height = window.physical_height().max(1);
+            }
+        }
+
         let mut sprite = query.single_mut();
-        sprite.custom_size = Some(Vec2::new(
-            event.width,
-            event.height,
+        sprite.custom_size = Some(get_sprite_size(
+            Vec2::new(event.width, event.height),
+            config.as_ref(),
         ));
     }
 }
 
-#[derive(Clone, Default, Serialize, Deserialize, Resource)]
+// Keeps the canvas fitted to the window when its size changes without the window being resized
+fn fit_sprite(config: Res<AppConfig>, windows: Query<&Window, With<PrimaryWindow>>, mut sprites: Query<&mut Sprite>) {
+    if !config.is_changed() {
+        return;
+    }
+
+    let (Ok(window), Ok(mut sprite)) = (windows.get_single(), sprites.get_single_mut()) else {
+        return;
+    };
+
+    if window.width() > 0.0 && window.height() > 0.0 {
+        sprite.custom_size = Some(get_sprite_size(Vec2::new(window.width(), window.height()), config.as_ref()));
+    }
+}
+
+// The canvas is scaled to fit the window without stretching it, the rest of the window is letterboxed
+fn get_sprite_size(window_size: Vec2, config: &AppConfig) -> Vec2 {
+    let texture_size = Vec2::new(config.texture.width as f32, config.texture.height as f32);
+    texture_size * (window_size / texture_size).min_element()
+}
+
+// Applies edits to the simulation section of the config file while running, the other sections are only read on startup
+#[allow(clippy::too_many_arguments)]
+fn reload_config(
+    time: Res<Time>,
+    mut elapsed: Local<f32>,
+    mut last_modified: Local<Option<SystemTime>>,
+    source: Res<ConfigSource>,
+    mut config: ResMut<AppConfig>,
+    mut settings: ResMut<SimulationSettings>,
+    render_device: Res<RenderDevice>,
+) {
+    *elapsed += time.delta_seconds();
+
+    if *elapsed < CONFIG_POLL_INTERVAL {
+        return;
+    }
+
+    *elapsed = 0.0;
+
+    let Ok(modified) = fs::metadata(&source.path).and_then(|metadata| metadata.modified()) else {
+        return;
+    };
+
+    // The first check only remembers the file that was loaded on startup
+    let previous = last_modified.replace(modified);
+    if previous.is_none() || previous == Some(modified) {
+        return;
+    }
+
+    let reloaded = match fs::read_to_string(&source.path)
+        .map_err(|err| format!("Failed to read {}: {}", source.path.display(), err))
+        .and_then(|contents| parse_config(contents.as_str(), source.as_ref()))
+    {
+        Ok(reloaded) => reloaded,
+        Err(err) => {
+            error!("{}\nKeeping the current settings", err);
+            return;
+        },
+    };
+
+    // The canvas size is only taken from the file while it doesn't follow the window
+    let is_resized = reloaded.texture.width != config.texture.width || reloaded.texture.height != config.texture.height;
+    if is_resized && !(config.window.resizable && config.texture.follow_window) {
+        match reloaded.validate_texture_size(render_device.limits().max_texture_dimension_2d) {
+            Ok(()) => {
+                info!("Resized the canvas to {}x{}", reloaded.texture.width, reloaded.texture.height);
+                config.texture.width = reloaded.texture.width;
+                config.texture.height = reloaded.texture.height;
+            },
+            Err(err) => error!("Invalid config {}: {}\nKeeping the current canvas size", source.path.display(), err),
+        }
+    }
+
+    let limits = render_device.limits();
+    if let Err(err) = reloaded.validate_agent_count(limits.max_compute_workgroups_per_dimension, limits.max_storage_buffer_binding_size) {
+        error!("Invalid config {}: {}\nKeeping the current settings", source.path.display(), err);
+        return;
+    }
+
+    let simulation = reloaded.simulation;
+
+    info!("Reloaded the simulation settings from {}", source.path.display());
+
+    // Pausing is left to the inspector, the file only decides whether the simulation starts paused
+    let pause = settings.pause;
+    *settings = simulation.clone();
+    settings.pause = pause;
+
+    config.simulation = simulation;
+}
+
+// Where the config comes from and what the command line changes about it, kept around to apply the same changes when reloading
+#[derive(Resource)]
+struct ConfigSource {
+    path: PathBuf,
+    preset: Option<String>,
+    overrides: Vec<(String, toml::Value)>,
+}
+
+impl ConfigSource {
+    fn new(cli: &Cli) -> Self {
+        let overrides = [
+            ("texture.width", cli.texture_width.map(i64::from)),
+            ("texture.height", cli.texture_height.map(i64::from)),
+            ("simulation.num_agents", cli.agents.map(i64::from)),
+            ("simulation.seed", cli.seed.map(|seed| seed as i64)),
+        ];
+
+        Self {
+            path: cli.config.clone(),
+            preset: cli.preset.clone(),
+            overrides: overrides
+                .into_iter()
+                .filter_map(|(key, value)| value.map(|value| (key.to_string(), toml::Value::Integer(value))))
+                .chain(cli.overrides.iter().cloned())
+                .collect(),
+        }
+    }
+
+    // The preset replaces the simulation section apart from whether to start paused, the overrides are applied on top in order
+    fn apply(&self, mut table: toml::Table) -> Result<toml::Table, String> {
+        if let Some(preset) = &self.preset {
+            let mut simulation = toml::Table::try_from(load_preset(&get_presets_directory(&self.path), preset)?)
+                .map_err(|err| format!("Failed to convert preset {}: {}", preset, err))?;
+
+            match table.get("simulation").and_then(|simulation| simulation.get("pause")) {
+                Some(pause) => simulation.insert("pause".to_string(), pause.clone()),
+                None => simulation.remove("pause"),
+            };
+
+            table.insert("simulation".to_string(), toml::Value::Table(simulation));
+        }
+
+        for (key, value) in &self.overrides {
+            let mut parts = key.split('.').peekable();
+            let mut current = &mut table;
+
+            while let Some(part) = parts.next() {
+                if parts.peek().is_none() {
+                    current.insert(part.to_string(), value.clone());
+                    break;
+                }
+
+                current = current
+                    .entry(part)
+                    .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                    .as_table_mut()
+                    .ok_or_else(|| format!("Failed to override {}: {} is not a section", key, part))?;
+            }
+        }
+
+        Ok(table)
+    }
+}
+
+fn parse_delta_time(argument: &str) -> Result<f32, String> {
+    match argument.parse::<f32>() {
+        Ok(delta_time) if delta_time > 0.0 && delta_time.is_finite() => Ok(delta_time),
+        Ok(_) => Err("must be a positive number of seconds".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+// Values are read as TOML, so `--set pipeline.backend=cpu` and `--set pipeline.backend='"cpu"'` both work
+fn parse_override(argument: &str) -> Result<(String, toml::Value), String> {
+    let (key, value) = argument
+        .split_once('=')
+        .filter(|(key, _)| !key.is_empty())
+        .ok_or_else(|| format!("expected KEY=VALUE, got {}", argument))?;
+
+    let value = toml::from_str::<toml::Table>(format!("value = {}", value).as_str())
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()));
+
+    Ok((key.to_string(), value))
+}
+
+// A missing config file is created with the defaults, but one that exists is never overwritten
+fn load_config(source: &ConfigSource) -> Result<AppConfig, String> {
+    match fs::read_to_string(&source.path) {
+        Ok(contents) => parse_config(contents.as_str(), source),
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            let contents = toml::to_string(&AppConfig::default()).expect("default config to serialize");
+
+            if let Err(err) = fs::write(&source.path, contents.as_str()) {
+                eprintln!("Failed to write the default config to {}: {}", source.path.display(), err);
+            }
+
+            parse_config(contents.as_str(), source)
+        },
+        Err(err) => Err(format!("Failed to read {}: {}", source.path.display(), err)),
+    }
+}
+
+fn parse_config(contents: &str, source: &ConfigSource) -> Result<AppConfig, String> {
+    let path = source.path.display();
+
+    // Parsing the file on its own first points errors in it to their line
+    let mut config = toml::from_str::<AppConfig>(contents)
+        .map_err(|err| format!("Failed to parse {}: {}", path, err))?;
+
+    if source.preset.is_some() || !source.overrides.is_empty() {
+        let table = toml::from_str::<toml::Table>(contents)
+            .map_err(|err| format!("Failed to parse {}: {}", path, err))?;
+
+        config = toml::Value::Table(source.apply(table)?)
+            .try_into()
+            .map_err(|err| format!("Invalid command line override for {}: {}", path, err))?;
+    }
+
+    let errors = config.validate();
+
+    if errors.is_empty() {
+        Ok(config)
+    } else {
+        Err(format!("Invalid config {}:\n  {}", path, errors.join("\n  ")))
+    }
+}
+
+#[derive(Clone, Default, ExtractResource, Serialize, Deserialize, Resource)]
+#[serde(default, deny_unknown_fields)]
 pub struct AppConfig {
     window: WindowConfig,
     texture: TextureConfig,
+    pipeline: PipelineConfig,
+    recording: RecordingConfig,
+    simulation: SimulationSettings,
+}
+
+impl AppConfig {
+    // Only catches values that can't work at all, the texture size is also checked against the limits of the GPU once it is known
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.window.width == 0 || self.window.height == 0 {
+            errors.push(format!("window size must be at least 1x1, got {}x{}", self.window.width, self.window.height));
+        }
+        if self.texture.width == 0 || self.texture.height == 0 {
+            errors.push(format!("texture size must be at least 1x1, got {}x{}", self.texture.width, self.texture.height));
+        }
+        if self.pipeline.ticks_per_second == 0 {
+            errors.push("pipeline.ticks_per_second must be at least 1".to_string());
+        }
+        if self.pipeline.max_steps_per_frame == 0 {
+            errors.push("pipeline.max_steps_per_frame must be at least 1".to_string());
+        }
+        if self.recording.fps == 0 {
+            errors.push("recording.fps must be at least 1".to_string());
+        }
+        if self.simulation.num_agents == 0 {
+            errors.push("simulation.num_agents must be at least 1".to_string());
+        }
+        if self.simulation.species.len() > MAX_SPECIES {
+            errors.push(format!("simulation.species can have at most {} entries, got {}", MAX_SPECIES, self.simulation.species.len()));
+        }
+        if self.simulation.gradient.len() > MAX_GRADIENT_STOPS {
+            errors.push(format!("simulation.gradient can have at most {} stops, got {}", MAX_GRADIENT_STOPS, self.simulation.gradient.len()));
+        }
+
+        errors
+    }
+
+    pub fn validate_texture_size(&self, max_dimension: u32) -> Result<(), String> {
+        if self.texture.width > max_dimension || self.texture.height > max_dimension {
+            return Err(format!(
+                "texture size {}x{} is larger than the {}x{} supported by the GPU",
+                self.texture.width, self.texture.height, max_dimension, max_dimension,
+            ));
+        }
+
+        Ok(())
+    }
+
+    // The GPU keeps every agent in one storage buffer and dispatches them over rows of workgroups
+    pub fn validate_agent_count(&self, max_workgroups: u32, max_binding_size: u32) -> Result<(), String> {
+        if self.pipeline.backend == PipelineBackend::Cpu {
+            return Ok(());
+        }
+
+        let max_agents = (max_binding_size as u64 / std::mem::size_of::<Agent>() as u64)
+            .min(max_workgroups as u64 * max_workgroups as u64 * 16);
+
+        if self.simulation.num_agents as u64 > max_agents {
+            return Err(format!(
+                "simulation.num_agents {} is more than the {} supported by the GPU",
+                self.simulation.num_agents, max_agents,
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct WindowConfig {
     width: u32,
     height: u32,
@@ -117,9 +546,12 @@ impl Default for WindowConfig {
 }
 
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct TextureConfig {
     width: u32,
     height: u32,
+    follow_window: bool,
+    trail_format: TrailFormat,
 }
 
 impl Default for TextureConfig {
@@ -127,6 +559,136 @@ impl Default for TextureConfig {
         Self {
             width: 2560,
             height: 1440,
+            follow_window: false,
+            trail_format: TrailFormat::default(),
         }
     }
 }
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    backend: PipelineBackend,
+    ticks_per_second: u32,
+    max_steps_per_frame: u32,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            backend: PipelineBackend::default(),
+            ticks_per_second: 144,
+            max_steps_per_frame: 8,
+        }
+    }
+}
+
+// The command is split at whitespace and run without a shell, {width}, {height}, {fps} and {output} are filled in.
+// Raw RGBA frames are written to its standard input
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingConfig {
+    directory: PathBuf,
+    fps: u32,
+    format: RecordingFormat,
+    command: String,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("recordings"),
+            fps: 60,
+            format: RecordingFormat::default(),
+            command: String::from(
+                "ffmpeg -y -f rawvideo -pixel_format rgba -video_size {width}x{height} -framerate {fps} -i - \
+                -vf pad=ceil(iw/2)*2:ceil(ih/2)*2 -c:v libx264 -pix_fmt yuv420p {output}.mp4"
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingFormat {
+    #[default]
+    Png,
+    Pipe,
+}
+
+#[derive(Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PipelineBackend {
+    #[default]
+    Gpu,
+    Cpu,
+}
+
+#[derive(Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailFormat {
+    Rgba8Unorm,
+    #[default]
+    Rgba16Float,
+    R32Float,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_source(arguments: &[&str]) -> ConfigSource {
+        // The presets directory next to this config doesn't exist, so presets come from the bundled ones
+        let cli = Cli::try_parse_from(["slime_simulation", "--config", "missing/slime_simulation_config.toml"].iter().chain(arguments))
+            .expect("arguments to parse");
+
+        ConfigSource::new(&cli)
+    }
+
+    #[test]
+    fn parse_override_values() {
+        assert_eq!(parse_override("simulation.decay_rate=0.3"), Ok(("simulation.decay_rate".to_string(), toml::Value::Float(0.3))));
+        assert_eq!(parse_override("window.vsync=false"), Ok(("window.vsync".to_string(), toml::Value::Boolean(false))));
+        assert_eq!(parse_override("pipeline.backend=\"cpu\""), Ok(("pipeline.backend".to_string(), toml::Value::String("cpu".to_string()))));
+        // Anything that isn't a TOML value is taken as a bare string
+        assert_eq!(parse_override("pipeline.backend=cpu"), Ok(("pipeline.backend".to_string(), toml::Value::String("cpu".to_string()))));
+        assert_eq!(parse_override("recording.command=a=b"), Ok(("recording.command".to_string(), toml::Value::String("a=b".to_string()))));
+
+        assert!(parse_override("simulation.decay_rate").is_err());
+        assert!(parse_override("=0.3").is_err());
+    }
+
+    #[test]
+    fn apply_dotted_keys() {
+        let source = get_source(&["--set", "simulation.spawn_mode.mode=Ring", "--set", "texture.width=320"]);
+        let table = source.apply(toml::from_str("[texture]\nwidth = 100\nheight = 50").unwrap()).unwrap();
+
+        assert_eq!(table["texture"]["width"].as_integer(), Some(320));
+        assert_eq!(table["texture"]["height"].as_integer(), Some(50));
+        // Missing sections are created along the way
+        assert_eq!(table["simulation"]["spawn_mode"]["mode"].as_str(), Some("Ring"));
+    }
+
+    #[test]
+    fn apply_rejects_keys_below_values() {
+        let source = get_source(&["--set", "texture.width.x=1"]);
+        let err = source.apply(toml::from_str("[texture]\nwidth = 100").unwrap()).unwrap_err();
+
+        assert_eq!(err, "Failed to override texture.width.x: width is not a section");
+    }
+
+    // The preset replaces the simulation section of the config apart from `pause`, and the command line goes on top of both
+    #[test]
+    fn apply_preset_then_overrides() {
+        let source = get_source(&["--preset", "01_tricolour", "--agents", "1234", "--set", "simulation.diffuse_rate=5.0"]);
+        let table = source.apply(toml::from_str("[simulation]\npause = false\ndecay_rate = 0.9\nbrush_size = 77.0\nnum_agents = 10").unwrap()).unwrap();
+        let simulation = toml::Value::Table(table).try_into::<AppConfig>().unwrap().simulation;
+
+        assert!(!simulation.pause);
+        assert_eq!(simulation.decay_rate, 0.15);
+        assert_eq!(simulation.diffuse_rate, 5.0);
+        assert_eq!(simulation.num_agents, 1234);
+        assert_eq!(simulation.brush_size, SimulationSettings::default().brush_size);
+        assert_eq!(simulation.species.len(), 3);
+    }
+}
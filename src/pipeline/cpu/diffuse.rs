@@ -0,0 +1,99 @@
+use bevy::prelude::*;
+
+use crate::pipeline::cpu::{get_boundary_location, is_obstacle, TrailMap};
+use crate::pipeline::diffuse::DiffusePipelineContext;
+
+pub fn diffuse(texture: &mut TrailMap, blurred: &mut TrailMap, obstacles: &TrailMap, context: &DiffusePipelineContext) {
+    if context.pause == 1 {
+        return;
+    }
+
+    let weights = (0..=context.radius as i32).map(|offset| get_weight(context, offset)).collect::<Vec<f32>>();
+
+    for y in 0..context.height as i32 {
+        for x in 0..context.width as i32 {
+            let location = IVec2::new(x, y);
+            let value = blur(texture, obstacles, location, IVec2::X, &weights, context);
+
+            blurred.store(location, value);
+        }
+    }
+
+    let diffuse_weight = (context.diffuse_rate * context.delta_time).min(1.0);
+
+    for y in 0..context.height as i32 {
+        for x in 0..context.width as i32 {
+            let location = IVec2::new(x, y);
+            let diffused_value = texture.load(location).lerp(blur(blurred, obstacles, location, IVec2::Y, &weights, context), diffuse_weight);
+
+            let mut decayed_value = match (context.has_trails, context.decay_mode) {
+                (1, 1) => diffused_value * (-context.decay_rate * context.delta_time * 5.0).exp(),
+                (1, _) => (diffused_value - Vec4::splat(context.decay_rate * context.delta_time * 5.0)).max(Vec4::ZERO),
+                _ => (diffused_value - Vec4::splat(0.3)).max(Vec4::ZERO),
+            };
+
+            if is_obstacle(obstacles, location) {
+                decayed_value = Vec4::ZERO;
+            }
+
+            texture.store(location, decayed_value);
+        }
+    }
+}
+
+// Mirrors paint in diffuse.wgsl
+pub fn paint(texture: &mut TrailMap, blurred: &mut TrailMap, obstacles: &TrailMap, context: &DiffusePipelineContext) {
+    let brush = &context.brush;
+
+    if brush.is_active != 1 || brush.tool >= 2 {
+        return;
+    }
+
+    for y in 0..context.height as i32 {
+        for x in 0..context.width as i32 {
+            let location = IVec2::new(x, y);
+            let distance = (location.as_vec2() + 0.5).distance(Vec2::from(brush.position));
+
+            if distance >= brush.size || is_obstacle(obstacles, location) {
+                continue;
+            }
+
+            let falloff = 1.0 - distance / brush.size;
+            let painted_value = texture.load(location).lerp(Vec4::from(brush.paint), (brush.strength * falloff * brush.delta_time * 10.0).min(1.0));
+
+            texture.store(location, painted_value);
+            blurred.store(location, painted_value);
+        }
+    }
+}
+
+// Mirrors getWeight in diffuse.wgsl
+fn get_weight(context: &DiffusePipelineContext, offset: i32) -> f32 {
+    if context.kernel == 1 {
+        let sigma = (context.radius as f32 / 2.0).max(0.5);
+        return (-(offset * offset) as f32 / (2.0 * sigma * sigma)).exp();
+    }
+
+    1.0
+}
+
+// `weights` holds the kernel weight for every offset from 0 up to the radius
+fn blur(texture: &TrailMap, obstacles: &TrailMap, location: IVec2, direction: IVec2, weights: &[f32], context: &DiffusePipelineContext) -> Vec4 {
+    let mut sum = texture.load(location) * weights[0];
+    let mut total_weight = weights[0];
+
+    for sign in [-1, 1] {
+        for (offset, weight) in weights.iter().enumerate().skip(1) {
+            let current_location = get_boundary_location(location + direction * sign * offset as i32, context.width, context.height, context.boundary_mode);
+
+            if texture.get_index(current_location).is_none() || is_obstacle(obstacles, current_location) {
+                break;
+            }
+
+            sum += texture.load(current_location) * *weight;
+            total_weight += weight;
+        }
+    }
+
+    sum / total_weight.max(0.0001)
+}
@@ -0,0 +1,553 @@
+use std::num::NonZeroU32;
+
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::*;
+use bevy::render::renderer::{RenderContext, RenderDevice, RenderQueue};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::AppConfig;
+use crate::pipeline::diffuse::DiffusePipelineContext;
+use crate::pipeline::maps::{get_food_map, load_obstacle_map};
+use crate::pipeline::recolor::RecolorPipelineContext;
+use crate::pipeline::simulation::{Agent, SimulationPipelineContext, SpeciesPipelineContext};
+use crate::pipeline::spawn::{resize_agents, spawn_agents};
+use crate::pipeline::{DISPLAY_FORMAT, FOOD_FORMAT, get_trail_format, OBSTACLE_FORMAT, PipelineImages, SubShaderPipeline};
+use crate::plugin::{BrushInput, FoodSettings, PluginTime, SimulationSettings};
+
+pub mod diffuse;
+pub mod recolor;
+pub mod simulation;
+
+#[derive(Default)]
+pub struct CpuShaderPipeline {
+    simulation: Option<CpuSimulation>,
+    staging_buffer: Option<Buffer>,
+    output_texture: Option<Texture>,
+}
+
+impl SubShaderPipeline for CpuShaderPipeline {
+    fn init_data(&mut self, render_device: &RenderDevice, app_config: &AppConfig, settings: &SimulationSettings) {
+        self.simulation = Some(CpuSimulation::new(app_config, settings));
+        self.staging_buffer = Some(create_staging_buffer(render_device, app_config));
+    }
+
+    fn prepare_data(&mut self, render_device: &RenderDevice, render_queue: &RenderQueue, app_config: &AppConfig, settings: &SimulationSettings, time: &PluginTime, brush: &BrushInput) {
+        let simulation = self.simulation.as_mut().expect("cpu simulation to exist");
+
+        if simulation.display.width != app_config.texture.width || simulation.display.height != app_config.texture.height {
+            simulation.resize(app_config);
+            simulation.load_obstacles(app_config, settings);
+            simulation.load_food(app_config, settings);
+            self.staging_buffer = Some(create_staging_buffer(render_device, app_config));
+        }
+
+        if simulation.obstacle_map != settings.obstacle_map {
+            simulation.load_obstacles(app_config, settings);
+        }
+
+        if simulation.food_settings.as_ref() != Some(&FoodSettings::new(settings)) {
+            simulation.load_food(app_config, settings);
+        }
+
+        if simulation.agents.len() != settings.num_agents as usize {
+            resize_agents(&mut simulation.agents, app_config, settings);
+        }
+
+        simulation.paint(app_config, settings, time, brush);
+        for step in 0..time.steps {
+            simulation.step(app_config, settings, time, brush, step);
+        }
+        simulation.render(settings);
+
+        render_queue.write_buffer(
+            self.staging_buffer.as_ref().expect("staging buffer to exist"),
+            0,
+            &simulation.output_image().to_rgba8(get_padded_bytes_per_row(app_config)),
+        );
+    }
+
+    fn queue_bind_groups(&mut self, _render_device: &RenderDevice, gpu_images: &RenderAssets<Image>, images: &PipelineImages) {
+        self.output_texture = Some(gpu_images[&images.display].texture.clone());
+    }
+
+    fn get_pipeline(&self) -> CachedComputePipelineId {
+        CachedComputePipelineId::INVALID
+    }
+
+    fn get_bind_group(&self) -> Option<&BindGroup> {
+        None
+    }
+
+    fn is_per_step(&self) -> bool {
+        false
+    }
+
+    fn is_ready(&self, _pipeline_cache: &PipelineCache) -> bool {
+        true
+    }
+
+    fn run(&self, render_context: &mut RenderContext, world: &World, _step: u32) {
+        let app_config = world.resource::<AppConfig>();
+
+        let (Some(buffer), Some(texture)) = (self.staging_buffer.as_ref(), self.output_texture.as_ref()) else {
+            return;
+        };
+
+        render_context.command_encoder().copy_buffer_to_texture(
+            ImageCopyBuffer {
+                buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(get_padded_bytes_per_row(app_config) as u32),
+                    rows_per_image: None,
+                },
+            },
+            texture.as_image_copy(),
+            Extent3d {
+                width: app_config.texture.width,
+                height: app_config.texture.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+fn create_staging_buffer(render_device: &RenderDevice, app_config: &AppConfig) -> Buffer {
+    render_device
+        .create_buffer(
+            &BufferDescriptor {
+                label: Some("cpu trail staging buffer"),
+                size: (get_padded_bytes_per_row(app_config) * app_config.texture.height as usize) as u64,
+                usage: BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            },
+        )
+}
+
+fn get_padded_bytes_per_row(app_config: &AppConfig) -> usize {
+    RenderDevice::align_copy_bytes_per_row(app_config.texture.width as usize * 4)
+}
+
+pub struct CpuSimulation {
+    agents: Vec<Agent>,
+    images: [TrailMap; 2],
+    display: TrailMap,
+    obstacles: TrailMap,
+    obstacle_map: String,
+    food: TrailMap,
+    food_settings: Option<FoodSettings>,
+}
+
+impl CpuSimulation {
+    pub fn new(app_config: &AppConfig, settings: &SimulationSettings) -> Self {
+        let mut rng = StdRng::seed_from_u64(settings.seed);
+
+        Self {
+            agents: spawn_agents(app_config, settings, &mut rng),
+            images: [
+                TrailMap::new(app_config.texture.width, app_config.texture.height, get_trail_format(app_config)),
+                TrailMap::new(app_config.texture.width, app_config.texture.height, get_trail_format(app_config)),
+            ],
+            display: TrailMap::new(app_config.texture.width, app_config.texture.height, DISPLAY_FORMAT),
+            obstacles: TrailMap::new(app_config.texture.width, app_config.texture.height, OBSTACLE_FORMAT),
+            obstacle_map: String::new(),
+            food: TrailMap::new(app_config.texture.width, app_config.texture.height, FOOD_FORMAT),
+            food_settings: None,
+        }
+    }
+
+    pub fn load_obstacles(&mut self, app_config: &AppConfig, settings: &SimulationSettings) {
+        self.obstacles = TrailMap::new(app_config.texture.width, app_config.texture.height, OBSTACLE_FORMAT);
+        self.obstacles.texels = load_obstacle_map(&settings.obstacle_map, app_config.texture.width, app_config.texture.height)
+            .into_iter()
+            .map(|value| Vec4::new(value as f32 / 255.0, 0.0, 0.0, 1.0))
+            .collect();
+        self.obstacle_map = settings.obstacle_map.clone();
+    }
+
+    pub fn load_food(&mut self, app_config: &AppConfig, settings: &SimulationSettings) {
+        self.food = TrailMap::new(app_config.texture.width, app_config.texture.height, FOOD_FORMAT);
+        self.food.texels = get_food_map(settings, app_config.texture.width, app_config.texture.height)
+            .into_iter()
+            .map(|value| Vec4::new(value, 0.0, 0.0, 1.0))
+            .collect();
+        self.food_settings = Some(FoodSettings::new(settings));
+    }
+
+    pub fn step(&mut self, app_config: &AppConfig, settings: &SimulationSettings, time: &PluginTime, brush: &BrushInput, step: u32) {
+        let [image_0, image_1] = &mut self.images;
+
+        simulation::update(
+            &mut self.agents,
+            image_1,
+            image_0,
+            &self.obstacles,
+            &self.food,
+            &SimulationPipelineContext::new(app_config, settings, time, brush, step),
+            &SpeciesPipelineContext::new(settings),
+        );
+        diffuse::diffuse(image_0, image_1, &self.obstacles, &DiffusePipelineContext::new(app_config, settings, time, brush));
+        image_1.texels.clone_from(&image_0.texels);
+    }
+
+    // Runs once per frame before the steps, like the paint pass of the GPU pipeline
+    pub fn paint(&mut self, app_config: &AppConfig, settings: &SimulationSettings, time: &PluginTime, brush: &BrushInput) {
+        let [image_0, image_1] = &mut self.images;
+
+        diffuse::paint(image_0, image_1, &self.obstacles, &DiffusePipelineContext::new(app_config, settings, time, brush));
+    }
+
+    // Mirrors rescale.wgsl, the trail maps start out empty again at the new size
+    pub fn resize(&mut self, app_config: &AppConfig) {
+        let scale = Vec2::new(app_config.texture.width as f32, app_config.texture.height as f32)
+            / Vec2::new(self.display.width as f32, self.display.height as f32);
+
+        for agent in &mut self.agents {
+            agent.position = (Vec2::from(agent.position) * scale).to_array();
+        }
+
+        self.images = [
+            TrailMap::new(app_config.texture.width, app_config.texture.height, get_trail_format(app_config)),
+            TrailMap::new(app_config.texture.width, app_config.texture.height, get_trail_format(app_config)),
+        ];
+        self.display = TrailMap::new(app_config.texture.width, app_config.texture.height, DISPLAY_FORMAT);
+    }
+
+    pub fn render(&mut self, settings: &SimulationSettings) {
+        recolor::recolor(&self.images[1], &self.food, &mut self.display, &RecolorPipelineContext::new(settings));
+    }
+
+    pub fn output_image(&self) -> &TrailMap {
+        &self.display
+    }
+}
+
+#[derive(Clone)]
+pub struct TrailMap {
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    texels: Vec<Vec4>,
+}
+
+impl TrailMap {
+    pub fn new(width: u32, height: u32, format: TextureFormat) -> Self {
+        Self {
+            width,
+            height,
+            format,
+            texels: vec![Vec4::ZERO; (width * height) as usize],
+        }
+    }
+
+    pub fn load(&self, location: IVec2) -> Vec4 {
+        self.get_index(location)
+            .map(|index| self.texels[index])
+            .unwrap_or(Vec4::ZERO)
+    }
+
+    pub fn store(&mut self, location: IVec2, value: Vec4) {
+        if let Some(index) = self.get_index(location) {
+            self.texels[index] = quantize(value, self.format);
+        }
+    }
+
+    pub fn is_single_channel(&self) -> bool {
+        self.format == TextureFormat::R32Float
+    }
+
+    pub fn to_rgba8(&self, bytes_per_row: usize) -> Vec<u8> {
+        let mut bytes = vec![0; bytes_per_row * self.height as usize];
+
+        for (row, texels) in bytes.chunks_exact_mut(bytes_per_row).zip(self.texels.chunks_exact(self.width as usize)) {
+            for (pixel, texel) in row.chunks_exact_mut(4).zip(texels) {
+                pixel.copy_from_slice(&(*texel * 255.0).round().to_array().map(|channel| channel as u8));
+            }
+        }
+
+        bytes
+    }
+
+    fn get_index(&self, location: IVec2) -> Option<usize> {
+        if location.x >= 0 && location.x < self.width as i32 && location.y >= 0 && location.y < self.height as i32 {
+            Some((location.y as u32 * self.width + location.x as u32) as usize)
+        } else {
+            None
+        }
+    }
+}
+
+// Mirrors getBoundaryLocation in common.wgsl
+pub(crate) fn get_boundary_location(location: IVec2, width: u32, height: u32, boundary_mode: u32) -> IVec2 {
+    let size = IVec2::new(width as i32, height as i32);
+
+    match boundary_mode {
+        1 => IVec2::new(location.x.rem_euclid(size.x), location.y.rem_euclid(size.y)),
+        2 => {
+            let mirrored = IVec2::select(location.cmplt(IVec2::ZERO), -location - 1, location);
+            let mirrored = IVec2::select(mirrored.cmpge(size), 2 * size - mirrored - 1, mirrored);
+            mirrored.clamp(IVec2::ZERO, size - 1)
+        },
+        _ => location,
+    }
+}
+
+// Mirrors isObstacle in common.wgsl
+pub(crate) fn is_obstacle(obstacles: &TrailMap, location: IVec2) -> bool {
+    obstacles.load(location).x > 0.5
+}
+
+// Matches the precision of the storage textures used by the GPU backend
+pub(crate) fn quantize(value: Vec4, format: TextureFormat) -> Vec4 {
+    match format {
+        TextureFormat::Rgba16Float => Vec4::from_array(value.to_array().map(round_to_f16)),
+        // Single channel textures load back with the missing channels set to (0, 0, 1)
+        TextureFormat::R32Float => Vec4::new(value.x, 0.0, 0.0, 1.0),
+        _ => (value.clamp(Vec4::ZERO, Vec4::ONE) * 255.0).round() / 255.0,
+    }
+}
+
+const F16_MAX: f32 = 65504.0;
+const F16_MIN_POSITIVE: f32 = 6.1035156e-5;
+
+// Rounds to the nearest half float, ties to even. Below the smallest normal half the values are evenly spaced
+// 2^-24 apart, and values too large for a half are clamped to the largest one instead of becoming infinite
+fn round_to_f16(value: f32) -> f32 {
+    if value.abs() < F16_MIN_POSITIVE {
+        return (value * 16777216.0).round_ties_even() / 16777216.0;
+    }
+
+    let bits = value.to_bits();
+    f32::from_bits((bits + 0xfff + ((bits >> 13) & 1)) & !0x1fff).clamp(-F16_MAX, F16_MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TrailFormat;
+    use crate::plugin::{BoundaryMode, DecayMode, DiffuseKernel, SpawnMode};
+
+    fn get_test_config(seed: u64) -> (AppConfig, SimulationSettings) {
+        let mut app_config = AppConfig::default();
+        app_config.texture.width = 64;
+        app_config.texture.height = 48;
+
+        let settings = SimulationSettings {
+            pause: false,
+            seed,
+            num_agents: 500,
+            ..default()
+        };
+
+        (app_config, settings)
+    }
+
+    fn run_simulation(app_config: &AppConfig, settings: &SimulationSettings, steps: u32) -> CpuSimulation {
+        let mut simulation = CpuSimulation::new(app_config, settings);
+        simulation.load_obstacles(app_config, settings);
+        simulation.load_food(app_config, settings);
+
+        for tick in 0..steps {
+            let time = PluginTime {
+                delta_time: 1.0 / 60.0,
+                tick,
+                steps: 1,
+            };
+
+            simulation.step(app_config, settings, &time, &BrushInput::default(), 0);
+        }
+
+        simulation.render(settings);
+        simulation
+    }
+
+    fn get_agent_bytes(simulation: &CpuSimulation) -> &[u8] {
+        bytemuck::cast_slice(&simulation.agents)
+    }
+
+    #[test]
+    fn same_seed_replays_identically() {
+        let (app_config, settings) = get_test_config(7);
+
+        let first = run_simulation(&app_config, &settings, 20);
+        let second = run_simulation(&app_config, &settings, 20);
+
+        assert_eq!(get_agent_bytes(&first), get_agent_bytes(&second));
+        assert_eq!(first.images[1].texels, second.images[1].texels);
+        assert_eq!(first.display.texels, second.display.texels);
+        assert!(first.images[1].texels.iter().any(|texel| *texel != Vec4::ZERO));
+    }
+
+    #[test]
+    fn different_seeds_differ() {
+        let (app_config, settings) = get_test_config(7);
+        let (_, other_settings) = get_test_config(8);
+
+        let first = run_simulation(&app_config, &settings, 20);
+        let second = run_simulation(&app_config, &other_settings, 20);
+
+        assert_ne!(get_agent_bytes(&first), get_agent_bytes(&second));
+        assert_ne!(first.images[1].texels, second.images[1].texels);
+    }
+
+    // A full strength box blur spreads the texel evenly over its 3x3 neighbourhood before it decays
+    #[test]
+    fn diffuse_and_decay_a_single_texel() {
+        let mut app_config = AppConfig::default();
+        app_config.texture.width = 5;
+        app_config.texture.height = 5;
+        app_config.texture.trail_format = TrailFormat::R32Float;
+
+        let time = PluginTime {
+            delta_time: 0.5,
+            tick: 0,
+            steps: 1,
+        };
+
+        for (decay_mode, decay_rate, expected) in [
+            (DecayMode::Subtractive, 0.02, 1.0 / 9.0 - 0.02 * 0.5 * 5.0),
+            (DecayMode::Multiplicative, 0.2, 1.0 / 9.0 * (-0.2f32 * 0.5 * 5.0).exp()),
+        ] {
+            let settings = SimulationSettings {
+                pause: false,
+                decay_mode,
+                decay_rate,
+                diffuse_kernel: DiffuseKernel::Box,
+                diffuse_radius: 1,
+                diffuse_rate: 2.0,
+                ..default()
+            };
+
+            let mut texture = TrailMap::new(5, 5, get_trail_format(&app_config));
+            let mut blurred = texture.clone();
+            let obstacles = TrailMap::new(5, 5, OBSTACLE_FORMAT);
+            texture.store(IVec2::new(2, 2), Vec4::new(1.0, 0.0, 0.0, 1.0));
+
+            diffuse::diffuse(&mut texture, &mut blurred, &obstacles, &DiffusePipelineContext::new(&app_config, &settings, &time, &BrushInput::default()));
+
+            for y in 0..5 {
+                for x in 0..5 {
+                    let value = texture.load(IVec2::new(x, y)).x;
+                    let is_neighbour = (x - 2).abs() <= 1 && (y - 2).abs() <= 1;
+
+                    if is_neighbour {
+                        assert!((value - expected).abs() < 1e-6, "texel ({}, {}) is {} instead of {}", x, y, value, expected);
+                    } else {
+                        assert_eq!(value, 0.0, "texel ({}, {}) is not empty", x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_to_f16_matches_half_floats() {
+        assert_eq!(round_to_f16(1.0), 1.0);
+        assert_eq!(round_to_f16(0.1), 0.099975586);
+        // Halfway between two halves rounds to the even one
+        assert_eq!(round_to_f16(1.0 + 1.0 / 2048.0), 1.0);
+        assert_eq!(round_to_f16(1.0 + 3.0 / 2048.0), 1.0 + 2.0 / 1024.0);
+
+        let smallest_subnormal = 1.0 / 16777216.0;
+        assert_eq!(round_to_f16(smallest_subnormal), smallest_subnormal);
+        assert_eq!(round_to_f16(smallest_subnormal * 0.4), 0.0);
+        assert_eq!(round_to_f16(smallest_subnormal * 2.6), smallest_subnormal * 3.0);
+        assert_eq!(round_to_f16(-smallest_subnormal * 2.6), -smallest_subnormal * 3.0);
+        assert_eq!(round_to_f16(F16_MIN_POSITIVE), F16_MIN_POSITIVE);
+
+        assert_eq!(round_to_f16(65519.0), F16_MAX);
+        assert_eq!(round_to_f16(1e6), F16_MAX);
+        assert_eq!(round_to_f16(-1e6), -F16_MAX);
+        assert_eq!(round_to_f16(f32::INFINITY), F16_MAX);
+    }
+
+    #[test]
+    fn quantize_per_format() {
+        let value = Vec4::new(0.1, 1.5, -0.2, 0.5);
+
+        assert_eq!(quantize(value, TextureFormat::Rgba8Unorm), Vec4::new(26.0 / 255.0, 1.0, 0.0, 128.0 / 255.0));
+        assert_eq!(quantize(value, TextureFormat::Rgba16Float), Vec4::new(0.099975586, 1.5, -0.19995117, 0.5));
+        assert_eq!(quantize(value, TextureFormat::R32Float), Vec4::new(0.1, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn boundary_location_per_mode() {
+        let get_location = |x, y, boundary_mode: BoundaryMode| get_boundary_location(IVec2::new(x, y), 4, 3, boundary_mode as u32);
+
+        for boundary_mode in [BoundaryMode::Clamp, BoundaryMode::Wrap, BoundaryMode::Bounce] {
+            assert_eq!(get_location(2, 1, boundary_mode), IVec2::new(2, 1));
+        }
+
+        // Clamped locations stay off the canvas and read as empty
+        assert_eq!(get_location(-1, 3, BoundaryMode::Clamp), IVec2::new(-1, 3));
+
+        assert_eq!(get_location(-1, 3, BoundaryMode::Wrap), IVec2::new(3, 0));
+        assert_eq!(get_location(9, -4, BoundaryMode::Wrap), IVec2::new(1, 2));
+
+        assert_eq!(get_location(-1, 3, BoundaryMode::Bounce), IVec2::new(0, 2));
+        assert_eq!(get_location(5, -2, BoundaryMode::Bounce), IVec2::new(2, 1));
+        // Only a single reflection is taken, locations further off are clamped onto the canvas
+        assert_eq!(get_location(-20, 20, BoundaryMode::Bounce), IVec2::new(0, 0));
+    }
+
+    #[test]
+    fn agents_stay_on_the_canvas() {
+        for boundary_mode in [BoundaryMode::Clamp, BoundaryMode::Wrap, BoundaryMode::Bounce] {
+            let (app_config, mut settings) = get_test_config(3);
+            settings.boundary_mode = boundary_mode;
+            settings.species[0].agent_speed = 5.0;
+
+            let simulation = run_simulation(&app_config, &settings, 50);
+
+            for agent in &simulation.agents {
+                let position = Vec2::from(agent.position);
+                assert!(
+                    position.cmpge(Vec2::ZERO).all() && position.cmplt(Vec2::new(64.0, 48.0)).all(),
+                    "agent left the canvas at {} with {} boundaries", position, boundary_mode as u32,
+                );
+            }
+        }
+    }
+
+    // A wall across the middle of the canvas, agents never walk into it and no trail is left on it
+    #[test]
+    fn obstacles_block_agents_and_trails() {
+        let (app_config, mut settings) = get_test_config(5);
+        settings.spawn_mode = SpawnMode::Random;
+        settings.species[0].agent_speed = 3.0;
+
+        let mut simulation = CpuSimulation::new(&app_config, &settings);
+        simulation.load_food(&app_config, &settings);
+        for y in 0..48 {
+            for x in 28..36 {
+                simulation.obstacles.store(IVec2::new(x, y), Vec4::ONE);
+            }
+        }
+
+        let obstacles = simulation.obstacles.clone();
+        let is_in_wall = |agent: &Agent| is_obstacle(&obstacles, Vec2::from(agent.position).as_ivec2());
+
+        for tick in 0..30 {
+            let time = PluginTime {
+                delta_time: 1.0 / 60.0,
+                tick,
+                steps: 1,
+            };
+
+            let was_in_wall = simulation.agents.iter().map(is_in_wall).collect::<Vec<bool>>();
+            simulation.step(&app_config, &settings, &time, &BrushInput::default(), 0);
+
+            for (agent, was_in_wall) in simulation.agents.iter().zip(was_in_wall) {
+                assert!(was_in_wall || !is_in_wall(agent), "agent walked into the wall at {:?}", agent.position);
+            }
+        }
+
+        for y in 0..48 {
+            for x in 28..36 {
+                assert_eq!(simulation.images[1].load(IVec2::new(x, y)), Vec4::ZERO, "texel ({}, {}) of the wall has a trail", x, y);
+            }
+        }
+    }
+}
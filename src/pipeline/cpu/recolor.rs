@@ -0,0 +1,57 @@
+use bevy::prelude::*;
+
+use crate::pipeline::cpu::{quantize, TrailMap};
+use crate::pipeline::recolor::RecolorPipelineContext;
+
+pub fn recolor(texture_in: &TrailMap, food: &TrailMap, texture_out: &mut TrailMap, context: &RecolorPipelineContext) {
+    let format = texture_out.format;
+
+    texture_out.texels = texture_in.texels
+        .iter()
+        .zip(&food.texels)
+        .map(|(value, food)| {
+            let mut color = value.truncate();
+
+            if texture_in.is_single_channel() {
+                color = match context.num_stops {
+                    0 => Vec3::splat(value.x),
+                    _ => sample_gradient(context, value.x.min(1.0)),
+                };
+            } else if context.num_stops > 0 {
+                let mut intensity = value.w;
+
+                if context.deposit_mode == 0 {
+                    intensity = (0..context.num_species as usize).map(|index| value[index]).sum();
+                }
+
+                color = sample_gradient(context, intensity.min(1.0));
+            } else if context.deposit_mode == 0 {
+                color = Vec3::ZERO;
+
+                for (index, species_color) in context.colors.iter().enumerate().take(context.num_species as usize) {
+                    let species_color = Vec4::from(*species_color);
+                    color += species_color.truncate() * species_color.w * value[index];
+                }
+            }
+
+            if context.show_food == 1 {
+                let food_color = Vec4::from(context.food_color);
+                color = color.lerp(food_color.truncate(), food.x.min(1.0) * food_color.w);
+            }
+
+            quantize(color.min(Vec3::ONE).extend(1.0), format)
+        })
+        .collect();
+}
+
+fn sample_gradient(context: &RecolorPipelineContext, t: f32) -> Vec3 {
+    let stops = &context.stops[..context.num_stops as usize];
+    let mut color = Vec4::from(stops[0].color).truncate();
+
+    for (previous, next) in stops.iter().zip(&stops[1..]) {
+        let amount = ((t - previous.position) / (next.position - previous.position).max(0.0001)).clamp(0.0, 1.0);
+        color = color.lerp(Vec4::from(next.color).truncate(), amount);
+    }
+
+    color
+}
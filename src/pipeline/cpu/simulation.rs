@@ -0,0 +1,217 @@
+use bevy::prelude::*;
+
+use crate::pipeline::cpu::{get_boundary_location, is_obstacle, TrailMap};
+use crate::pipeline::simulation::{Agent, SimulationPipelineContext, SpeciesContext, SpeciesPipelineContext};
+
+pub fn hash(value: u32) -> u32 {
+    let mut state = value;
+    state ^= 2747636419;
+    state = state.wrapping_mul(2654435769);
+    state ^= state >> 16;
+    state = state.wrapping_mul(2654435769);
+    state ^= state >> 16;
+    state = state.wrapping_mul(2654435769);
+    state
+}
+
+pub fn scale_to_01(value: u32) -> f32 {
+    value as f32 / 4294967295.0
+}
+
+fn hue_to_rgb(hue: f32) -> Vec3 {
+    let hue = Vec3::splat(hue) + Vec3::new(0.0, 2.0 / 3.0, 1.0 / 3.0);
+    (((hue - hue.floor()) * 6.0 - 3.0).abs() - 1.0).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn sense(agent: &Agent, angle_offset: f32, species: &SpeciesContext, weights: Vec4, texture_in: &TrailMap, obstacles: &TrailMap, food: &TrailMap, context: &SimulationPipelineContext) -> f32 {
+    let angle = agent.angle + angle_offset;
+    let direction = Vec2::new(angle.cos(), angle.sin());
+    let sense_position = (Vec2::from(agent.position) + direction * species.sense_distance).as_ivec2();
+    let size = species.sensor_size as i32;
+
+    let mut sum = 0.0;
+
+    for x in -size..=size {
+        for y in -size..=size {
+            let location = get_boundary_location(sense_position + IVec2::new(x, y), context.width, context.height, context.boundary_mode);
+
+            if is_obstacle(obstacles, location) {
+                sum -= 1.0;
+            } else {
+                sum += texture_in.load(location).dot(weights) + food.load(location).x;
+            }
+        }
+    }
+
+    sum
+}
+
+// Unlike f32::signum, zero stays zero like sign in WGSL
+fn sign(value: f32) -> f32 {
+    if value > 0.0 {
+        1.0
+    } else if value < 0.0 {
+        -1.0
+    } else {
+        0.0
+    }
+}
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// Uses the same approximation of pi as simulation.wgsl so both backends turn by identical amounts
+#[allow(clippy::approx_constant)]
+pub fn update(
+    agents: &mut [Agent],
+    texture_in: &TrailMap,
+    texture_out: &mut TrailMap,
+    obstacles: &TrailMap,
+    food: &TrailMap,
+    context: &SimulationPipelineContext,
+    species_context: &SpeciesPipelineContext,
+) {
+    if context.pause == 1 {
+        return;
+    }
+
+    let step_seed = hash(context.tick ^ hash(context.seed));
+
+    for (id, agent) in agents.iter_mut().enumerate() {
+        let mut random = hash(
+            (agent.position[0] as u32)
+                .wrapping_mul(context.width)
+                .wrapping_add(agent.position[1] as u32)
+                .wrapping_add(hash((id as u32).wrapping_add(step_seed)))
+        );
+
+        let species_index = (agent.species % species_context.num_species) as usize;
+        let species = &species_context.species[species_index];
+
+        let mut mask = Vec4::ZERO;
+        mask[species_index] = 1.0;
+        let mut weights = mask * 2.0 - 1.0;
+
+        if texture_in.is_single_channel() {
+            weights = Vec4::X;
+        } else if context.deposit_mode != 0 {
+            weights = Vec4::W;
+        }
+
+        let turn_speed = species.turn_speed * 3.1415 * 2.0 * 0.01;
+        let random_strength = scale_to_01(random);
+        let turn_randomness = species.turn_randomness.clamp(0.0, 1.0);
+
+        let sensor_count = species.sensor_count.max(1);
+
+        let mut forward = 0.0;
+        let mut has_forward = false;
+        let mut left = -1e30_f32;
+        let mut right = -1e30_f32;
+        let mut weighted_angle = 0.0;
+        let mut total_reading = 0.0;
+
+        for i in 0..sensor_count {
+            let mut sensor_angle = 0.0;
+            if sensor_count > 1 {
+                sensor_angle = species.sense_angle_offset * (2.0 * i as f32 / (sensor_count - 1) as f32 - 1.0);
+            }
+
+            let reading = sense(agent, sensor_angle, species, weights, texture_in, obstacles, food, context);
+
+            if sensor_angle < 0.0 {
+                left = left.max(reading);
+            } else if sensor_angle > 0.0 {
+                right = right.max(reading);
+            } else {
+                forward = reading;
+                has_forward = true;
+            }
+
+            weighted_angle += reading.max(0.0) * sensor_angle;
+            total_reading += reading.max(0.0);
+        }
+
+        let side = sign(right - left);
+
+        if species.sensor_weighting == 1 {
+            if total_reading > 0.0 {
+                let steer = (weighted_angle / total_reading / species.sense_angle_offset.max(0.0001)).clamp(-1.0, 1.0);
+                agent.angle += turn_speed * steer * mix(1.0, random_strength, turn_randomness);
+            }
+        } else if has_forward && forward > left && forward > right {
+            // Keep going straight
+        } else if has_forward && forward < left && forward < right {
+            let random_side = if random_strength < 0.5 { -1.0 } else { 1.0 };
+            agent.angle += turn_speed * random_side * mix(1.0, (random_strength * 2.0).fract(), turn_randomness);
+        } else {
+            agent.angle += turn_speed * side * mix(1.0, random_strength, turn_randomness);
+        }
+
+        let brush = &context.brush;
+
+        if brush.is_active == 1 && brush.tool >= 2 {
+            let offset = Vec2::from(brush.position) - Vec2::from(agent.position);
+            let distance = offset.length();
+
+            if distance > 0.0 && distance < brush.size {
+                let mut target_angle = offset.y.atan2(offset.x);
+                if brush.tool == 3 {
+                    target_angle += 3.1415;
+                }
+
+                let angle_difference = target_angle - agent.angle;
+                let falloff = 1.0 - distance / brush.size;
+                agent.angle += angle_difference.sin().atan2(angle_difference.cos()) * (brush.strength * falloff * context.delta_time * 10.0).min(1.0);
+            }
+        }
+
+        let direction = Vec2::new(agent.angle.cos(), agent.angle.sin());
+        let mut new_position = Vec2::from(agent.position) + direction * species.speed * context.delta_time * 50.0;
+
+        let size = Vec2::new(context.width as f32, context.height as f32);
+
+        if context.boundary_mode == 1 {
+            new_position -= (new_position / size).floor() * size;
+        } else if context.boundary_mode == 2 {
+            if new_position.x < 0.0 || new_position.x >= size.x {
+                new_position.x = if new_position.x < 0.0 { -new_position.x } else { 2.0 * size.x - new_position.x };
+                agent.angle = 3.1415 - agent.angle;
+            }
+            if new_position.y < 0.0 || new_position.y >= size.y {
+                new_position.y = if new_position.y < 0.0 { -new_position.y } else { 2.0 * size.y - new_position.y };
+                agent.angle = -agent.angle;
+            }
+
+            new_position = new_position.clamp(Vec2::ZERO, size - 1.0);
+        } else if new_position.x < 0.0 || new_position.x >= context.width as f32 || new_position.y < 0.0 || new_position.y >= context.height as f32 {
+            new_position = Vec2::new(
+                (context.width as f32 - 1.0).min(new_position.x.max(1.0)),
+                (context.height as f32 - 1.0).min(new_position.y.max(1.0)),
+            );
+
+            random = hash(random);
+            agent.angle = scale_to_01(random) * 3.1415 * 2.0;
+        }
+
+        if is_obstacle(obstacles, new_position.as_ivec2()) && !is_obstacle(obstacles, Vec2::from(agent.position).as_ivec2()) {
+            new_position = Vec2::from(agent.position);
+
+            random = hash(random);
+            agent.angle = scale_to_01(random) * 3.1415 * 2.0;
+        }
+
+        agent.position = new_position.to_array();
+
+        let deposit = match context.deposit_mode {
+            _ if texture_out.is_single_channel() => Vec4::ONE,
+            1 => Vec4::from(species.color).truncate().extend(1.0),
+            2 => hue_to_rgb(agent.angle / (3.1415 * 2.0)).extend(1.0),
+            _ => mask,
+        };
+
+        texture_out.store(new_position.as_ivec2(), deposit);
+    }
+}
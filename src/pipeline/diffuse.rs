@@ -0,0 +1,289 @@
+use bevy::core::{Pod, Zeroable};
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::*;
+use bevy::render::renderer::{RenderContext, RenderDevice, RenderQueue};
+
+use crate::AppConfig;
+use crate::pipeline::{get_compute_pipeline_id, get_shader_defs, get_trail_format, PipelineData, PipelineImages, run_shader, SubShaderPipeline};
+use crate::pipeline::simulation::BrushContext;
+use crate::plugin::{BrushInput, PluginTime, SimulationSettings};
+
+pub struct DiffuseShaderPipeline {
+    bind_group_layout: BindGroupLayout,
+    bind_group: Option<BindGroup>,
+    horizontal_pipeline: CachedComputePipelineId,
+    vertical_pipeline: CachedComputePipelineId,
+    paint_pipeline: CachedComputePipelineId,
+    context: PipelineData<DiffusePipelineContext>,
+    trail_textures: Option<[Texture; 2]>,
+}
+
+impl DiffuseShaderPipeline {
+    pub fn new(world: &mut World) -> Self {
+        let bind_group_layout = get_bind_group_layout(
+            world.resource::<RenderDevice>(),
+            get_trail_format(world.resource::<AppConfig>()),
+        );
+        let shader_defs = get_shader_defs(world.resource::<AppConfig>());
+
+        let shader = world.resource::<AssetServer>().load("shaders/diffuse.wgsl");
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+
+        Self {
+            horizontal_pipeline: get_compute_pipeline_id(
+                shader.clone(),
+                pipeline_cache.as_mut(),
+                bind_group_layout.clone(),
+                shader_defs.clone(),
+                "diffuse shader horizontal".to_string(),
+                "diffuseHorizontal".to_string(),
+            ),
+            vertical_pipeline: get_compute_pipeline_id(
+                shader.clone(),
+                pipeline_cache.as_mut(),
+                bind_group_layout.clone(),
+                shader_defs.clone(),
+                "diffuse shader vertical".to_string(),
+                "diffuseVertical".to_string(),
+            ),
+            paint_pipeline: get_compute_pipeline_id(
+                shader,
+                pipeline_cache.as_mut(),
+                bind_group_layout.clone(),
+                shader_defs,
+                "diffuse shader paint".to_string(),
+                "paint".to_string(),
+            ),
+            bind_group_layout,
+            bind_group: None,
+            context: PipelineData::default(),
+            trail_textures: None,
+        }
+    }
+}
+
+impl SubShaderPipeline for DiffuseShaderPipeline {
+    fn init_data(&mut self, render_device: &RenderDevice, _app_config: &AppConfig, _settings: &SimulationSettings) {
+        self.context.buffer = Some(render_device
+            .create_buffer(
+                &BufferDescriptor {
+                    label: Some("diffuse context uniform buffer"),
+                    size: std::mem::size_of::<DiffusePipelineContext>() as u64,
+                    usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                },
+            )
+        );
+    }
+
+    fn prepare_data(&mut self, _render_device: &RenderDevice, render_queue: &RenderQueue, app_config: &AppConfig, settings: &SimulationSettings, time: &PluginTime, brush: &BrushInput) {
+        self.context.data = Some(DiffusePipelineContext::new(app_config, settings, time, brush));
+
+        render_queue.write_buffer(
+            self.context.buffer.as_ref().expect("context buffer to exist"),
+            0,
+            bevy::core::cast_slice(&[
+                self.context.data.expect("context data to exist"),
+            ]),
+        );
+    }
+
+    fn queue_bind_groups(
+        &mut self,
+        render_device: &RenderDevice,
+        gpu_images: &RenderAssets<Image>,
+        images: &PipelineImages,
+    ) {
+        self.bind_group = Some(
+            render_device.create_bind_group(
+                &BindGroupDescriptor {
+                    label: Some("diffuse bind group"),
+                    layout: &self.bind_group_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::TextureView(
+                                &gpu_images[&images.trail[0]].texture_view,
+                            ),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::TextureView(
+                                &gpu_images[&images.trail[1]].texture_view,
+                            ),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: self.context.buffer
+                                .as_ref()
+                                .expect("context buffer to exist")
+                                .as_entire_binding(),
+                        },
+                        BindGroupEntry {
+                            binding: 3,
+                            resource: BindingResource::TextureView(
+                                &gpu_images[&images.obstacles].texture_view,
+                            ),
+                        },
+                    ],
+                },
+            ),
+        );
+
+        self.trail_textures = Some(images.trail.clone().map(|handle| gpu_images[&handle].texture.clone()));
+    }
+
+    fn get_pipeline(&self) -> CachedComputePipelineId {
+        self.vertical_pipeline
+    }
+
+    fn get_bind_group(&self) -> Option<&BindGroup> {
+        self.bind_group.as_ref()
+    }
+
+    fn is_ready(&self, pipeline_cache: &PipelineCache) -> bool {
+        [self.horizontal_pipeline, self.vertical_pipeline, self.paint_pipeline]
+            .into_iter()
+            .all(|pipeline| matches!(pipeline_cache.get_compute_pipeline_state(pipeline), CachedPipelineState::Ok(_)))
+    }
+
+    // The paint and erase brushes work on the trail map once per frame, the other tools steer the agents every step
+    fn run_before_steps(&self, render_context: &mut RenderContext, world: &World) {
+        if !self.context.data.is_some_and(|context| context.brush.is_active == 1 && context.brush.tool < 2) {
+            return;
+        }
+
+        run_shader(
+            render_context,
+            world.resource::<PipelineCache>(),
+            self.paint_pipeline,
+            self.get_bind_group(),
+            &[],
+            self.get_workgroup_size(world.resource::<AppConfig>(), world.resource::<SimulationSettings>()),
+        );
+    }
+
+    // The diffused trail map replaces the one the simulation deposited into,
+    // and is copied over so the next step senses it
+    fn run(&self, render_context: &mut RenderContext, world: &World, _step: u32) {
+        let app_config = world.resource::<AppConfig>();
+        let settings = world.resource::<SimulationSettings>();
+
+        for pipeline in [self.horizontal_pipeline, self.vertical_pipeline] {
+            run_shader(
+                render_context,
+                world.resource::<PipelineCache>(),
+                pipeline,
+                self.get_bind_group(),
+                &[],
+                self.get_workgroup_size(app_config, settings),
+            );
+        }
+
+        let Some([trail_0, trail_1]) = self.trail_textures.as_ref() else {
+            return;
+        };
+
+        render_context.command_encoder().copy_texture_to_texture(
+            trail_0.as_image_copy(),
+            trail_1.as_image_copy(),
+            Extent3d {
+                width: app_config.texture.width,
+                height: app_config.texture.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+fn get_bind_group_layout(render_device: &RenderDevice, trail_format: TextureFormat) -> BindGroupLayout {
+    render_device
+        .create_bind_group_layout(
+            &BindGroupLayoutDescriptor {
+                label: Some("diffuse bind group layout"),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::ReadWrite,
+                            format: trail_format,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::ReadWrite,
+                            format: trail_format,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(std::mem::size_of::<DiffusePipelineContext>() as u64),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float {
+                                filterable: false,
+                            },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
+            },
+        )
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+pub(crate) struct DiffusePipelineContext {
+    pub pause: u32,
+    pub width: u32,
+    pub height: u32,
+    pub radius: u32,
+    pub kernel: u32,
+    pub decay_mode: u32,
+    pub has_trails: u32,
+    pub diffuse_rate: f32,
+    pub decay_rate: f32,
+    pub delta_time: f32,
+    pub boundary_mode: u32,
+    pub _padding: u32,
+    pub brush: BrushContext,
+}
+
+impl DiffusePipelineContext {
+    pub fn new(app_config: &AppConfig, settings: &SimulationSettings, time: &PluginTime, brush: &BrushInput) -> Self {
+        Self {
+            pause: if settings.pause { 1 } else { 0 },
+            width: app_config.texture.width,
+            height: app_config.texture.height,
+            radius: settings.diffuse_radius,
+            kernel: settings.diffuse_kernel as u32,
+            decay_mode: settings.decay_mode as u32,
+            has_trails: if settings.has_trails { 1 } else { 0 },
+            diffuse_rate: settings.diffuse_rate,
+            decay_rate: settings.decay_rate,
+            delta_time: time.delta_time,
+            boundary_mode: settings.boundary_mode as u32,
+            _padding: 0,
+            brush: BrushContext::new(app_config, settings, brush),
+        }
+    }
+}
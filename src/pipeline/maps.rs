@@ -0,0 +1,54 @@
+use bevy::prelude::*;
+use image::imageops::FilterType;
+
+use crate::plugin::SimulationSettings;
+
+// One byte per texel, stretched to the canvas size. Anything brighter than half is an obstacle
+pub fn load_obstacle_map(path: &str, width: u32, height: u32) -> Vec<u8> {
+    load_greyscale_map(path, width, height, "obstacle map")
+        .unwrap_or_else(|| vec![0; (width * height) as usize])
+}
+
+// The food sources fade out linearly towards their radius, the food mask adds its brightness on top
+pub fn get_food_map(settings: &SimulationSettings, width: u32, height: u32) -> Vec<f32> {
+    let mut food = match load_greyscale_map(&settings.food_map, width, height, "food map") {
+        Some(mask) => mask
+            .into_iter()
+            .map(|value| value as f32 / 255.0 * settings.food_map_strength)
+            .collect(),
+        None => vec![0.0; (width * height) as usize],
+    };
+
+    let center = Vec2::new(width as f32, height as f32) / 2.0;
+
+    for source in &settings.food_sources {
+        let position = center + source.position;
+        let radius = source.radius.max(1.0);
+
+        let min = (position - radius).floor().max(Vec2::ZERO).as_uvec2();
+        let max = (position + radius).ceil().min(Vec2::new(width as f32, height as f32)).as_uvec2();
+
+        for y in min.y..max.y {
+            for x in min.x..max.x {
+                let distance = (Vec2::new(x as f32, y as f32) + 0.5).distance(position);
+                food[(y * width + x) as usize] += source.strength * (1.0 - distance / radius).max(0.0);
+            }
+        }
+    }
+
+    food
+}
+
+fn load_greyscale_map(path: &str, width: u32, height: u32, name: &str) -> Option<Vec<u8>> {
+    if path.is_empty() {
+        return None;
+    }
+
+    match image::open(path) {
+        Ok(image) => Some(image::imageops::resize(&image.into_luma8(), width, height, FilterType::Nearest).into_raw()),
+        Err(err) => {
+            error!("Failed to load {} {}, ignoring it instead: {}", name, path, err);
+            None
+        },
+    }
+}
@@ -7,32 +7,44 @@ use bevy::render::render_graph::{Node, NodeRunError, RenderGraphContext};
 use bevy::render::render_resource::*;
 use bevy::render::renderer::{RenderContext, RenderDevice, RenderQueue};
 
-use crate::AppConfig;
-use crate::pipeline::blur::BlurShaderPipeline;
-use crate::pipeline::fade::FadeShaderPipeline;
+use crate::{AppConfig, PipelineBackend, TrailFormat};
+use crate::pipeline::cpu::CpuShaderPipeline;
+use crate::pipeline::diffuse::DiffuseShaderPipeline;
 use crate::pipeline::recolor::RecolorShaderPipeline;
 use crate::pipeline::simulation::SimulationShaderPipeline;
-use crate::plugin::{PluginTime, SimulationSettings};
+use crate::plugin::{BrushInput, PluginTime, SimulationSettings};
 
-pub mod blur;
-pub mod fade;
+pub mod cpu;
+pub mod diffuse;
+pub mod maps;
+pub mod readback;
 pub mod recolor;
 pub mod simulation;
+pub mod spawn;
 
 #[derive(Resource)]
 pub struct MainShaderPipeline {
     sub_pipelines: Vec<Box<dyn SubShaderPipeline>>,
+    // Imported by the simulation and diffuse shaders, which only compile while it is loaded
+    _common_shader: Handle<Shader>,
 }
 
 impl FromWorld for MainShaderPipeline {
     fn from_world(world: &mut World) -> Self {
-        let mut pipeline = Self {
-            sub_pipelines: vec![
+        let sub_pipelines: Vec<Box<dyn SubShaderPipeline>> = match world.resource::<AppConfig>().pipeline.backend {
+            PipelineBackend::Gpu => vec![
                 Box::new(SimulationShaderPipeline::new(world)),
-                Box::new(FadeShaderPipeline::new(world)),
-                Box::new(BlurShaderPipeline::new(world)),
+                Box::new(DiffuseShaderPipeline::new(world)),
                 Box::new(RecolorShaderPipeline::new(world)),
             ],
+            PipelineBackend::Cpu => vec![
+                Box::new(CpuShaderPipeline::default()),
+            ],
+        };
+
+        let mut pipeline = Self {
+            sub_pipelines,
+            _common_shader: world.resource::<AssetServer>().load("shaders/common.wgsl"),
         };
 
         pipeline.init_data(world.resource::<RenderDevice>(), world.resource::<AppConfig>(), world.resource::<SimulationSettings>());
@@ -48,13 +60,15 @@ impl MainShaderPipeline {
     }
 
     pub fn prepare_data(&mut self,
+        render_device: &RenderDevice,
         render_queue: &RenderQueue,
         app_config: &AppConfig,
         settings: &SimulationSettings,
         time: &PluginTime,
+        brush: &BrushInput,
     ) {
         for sub_pipeline in &mut self.sub_pipelines {
-            sub_pipeline.prepare_data(render_queue, app_config, settings, time);
+            sub_pipeline.prepare_data(render_device, render_queue, app_config, settings, time, brush);
         }
     }
 
@@ -68,22 +82,30 @@ impl MainShaderPipeline {
             sub_pipeline.queue_bind_groups(
                 render_device.as_ref(),
                 gpu_images.as_ref(),
-                images.0.as_ref(),
+                images.as_ref(),
             )
         }
     }
 
-    fn run_shaders(&self, render_context: &mut RenderContext, world: &World) {
-        let pipeline_cache = world.resource::<PipelineCache>();
+    pub fn is_ready(&self, pipeline_cache: &PipelineCache) -> bool {
+        self.sub_pipelines
+            .iter()
+            .all(|sub_pipeline| sub_pipeline.is_ready(pipeline_cache))
+    }
 
+    fn run_shaders(&self, render_context: &mut RenderContext, world: &World) {
         for sub_pipeline in &self.sub_pipelines {
-            run_shader(
-                render_context,
-                pipeline_cache,
-                sub_pipeline.get_pipeline(),
-                sub_pipeline.get_bind_group(),
-                sub_pipeline.get_workgroup_size(world.resource::<AppConfig>(), world.resource::<SimulationSettings>()),
-            )
+            sub_pipeline.run_before_steps(render_context, world);
+        }
+
+        for step in 0..world.resource::<PluginTime>().steps {
+            for sub_pipeline in self.sub_pipelines.iter().filter(|sub_pipeline| sub_pipeline.is_per_step()) {
+                sub_pipeline.run(render_context, world, step);
+            }
+        }
+
+        for sub_pipeline in self.sub_pipelines.iter().filter(|sub_pipeline| !sub_pipeline.is_per_step()) {
+            sub_pipeline.run(render_context, world, 0);
         }
     }
 }
@@ -93,6 +115,7 @@ fn run_shader(
     pipeline_cache: &PipelineCache,
     pipeline: CachedComputePipelineId,
     bind_group: Option<&BindGroup>,
+    dynamic_offsets: &[u32],
     workgroup_size: WorkgroupSize,
 ) {
     if let CachedPipelineState::Ok(_) = pipeline_cache.get_compute_pipeline_state(pipeline) {
@@ -100,7 +123,7 @@ fn run_shader(
             .command_encoder()
             .begin_compute_pass(&ComputePassDescriptor::default());
 
-        compute_pass.set_bind_group(0, bind_group.expect("bind group to exist"), &[]);
+        compute_pass.set_bind_group(0, bind_group.expect("bind group to exist"), dynamic_offsets);
 
         let pipeline = pipeline_cache
             .get_compute_pipeline(pipeline)
@@ -119,6 +142,7 @@ fn get_compute_pipeline_id(
     shader: Handle<Shader>,
     pipeline_cache: &mut PipelineCache,
     bind_group_layout: BindGroupLayout,
+    shader_defs: Vec<ShaderDefVal>,
     label: String,
     entry_point: String,
 ) -> CachedComputePipelineId {
@@ -129,7 +153,7 @@ fn get_compute_pipeline_id(
                 layout: vec![bind_group_layout],
                 push_constant_ranges: vec![],
                 shader,
-                shader_defs: vec![],
+                shader_defs,
                 entry_point: Cow::from(entry_point),
             },
         )
@@ -137,19 +161,46 @@ fn get_compute_pipeline_id(
 
 pub trait SubShaderPipeline: Send + Sync {
     fn init_data(&mut self, _render_device: &RenderDevice, _app_config: &AppConfig, _settings: &SimulationSettings) {}
-    fn prepare_data(&mut self, _render_queue: &RenderQueue, _app_config: &AppConfig, _settings: &SimulationSettings, _time: &PluginTime) {}
+    fn prepare_data(&mut self, _render_device: &RenderDevice, _render_queue: &RenderQueue, _app_config: &AppConfig, _settings: &SimulationSettings, _time: &PluginTime, _brush: &BrushInput) {}
 
-    fn queue_bind_groups(&mut self, render_device: &RenderDevice, gpu_images: &RenderAssets<Image>, images: &Vec<Handle<Image>>);
+    fn queue_bind_groups(&mut self, render_device: &RenderDevice, gpu_images: &RenderAssets<Image>, images: &PipelineImages);
     fn get_pipeline(&self) -> CachedComputePipelineId;
     fn get_bind_group(&self) -> Option<&BindGroup>;
 
+    fn get_dynamic_offsets(&self, _step: u32) -> Vec<u32> {
+        Vec::new()
+    }
+
+    // Per step pipelines are dispatched once for every simulation tick, the others once per rendered frame
+    fn is_per_step(&self) -> bool {
+        true
+    }
+
     fn get_workgroup_size(&self, app_config: &AppConfig, _settings: &SimulationSettings) -> WorkgroupSize {
         WorkgroupSize {
-            x: app_config.texture.width / 8,
-            y: app_config.texture.height / 8,
+            x: app_config.texture.width.div_ceil(8),
+            y: app_config.texture.height.div_ceil(8),
             z: 1,
         }
     }
+
+    fn is_ready(&self, pipeline_cache: &PipelineCache) -> bool {
+        matches!(pipeline_cache.get_compute_pipeline_state(self.get_pipeline()), CachedPipelineState::Ok(_))
+    }
+
+    // Runs once every rendered frame, before the first step and even while paused
+    fn run_before_steps(&self, _render_context: &mut RenderContext, _world: &World) {}
+
+    fn run(&self, render_context: &mut RenderContext, world: &World, step: u32) {
+        run_shader(
+            render_context,
+            world.resource::<PipelineCache>(),
+            self.get_pipeline(),
+            self.get_bind_group(),
+            &self.get_dynamic_offsets(step),
+            self.get_workgroup_size(world.resource::<AppConfig>(), world.resource::<SimulationSettings>()),
+        )
+    }
 }
 
 pub struct PipelineData<T> {
@@ -166,15 +217,50 @@ impl<T> Default for PipelineData<T> {
     }
 }
 
-#[derive(Clone, Deref, ExtractResource, Resource)]
-pub struct PipelineImages(pub Vec<Handle<Image>>);
+// Only the simulation passes touch the trail maps, the display image is what ends up on screen.
+// The obstacle and food maps are only ever read by the shaders and are updated from the main world
+#[derive(Clone, ExtractResource, Resource)]
+pub struct PipelineImages {
+    pub trail: [Handle<Image>; 2],
+    pub display: Handle<Image>,
+    pub obstacles: Handle<Image>,
+    pub food: Handle<Image>,
+}
+
+pub const DISPLAY_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+pub const OBSTACLE_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+pub const FOOD_FORMAT: TextureFormat = TextureFormat::R32Float;
+
+pub fn get_trail_format(app_config: &AppConfig) -> TextureFormat {
+    match app_config.texture.trail_format {
+        TrailFormat::Rgba8Unorm => TextureFormat::Rgba8Unorm,
+        TrailFormat::Rgba16Float => TextureFormat::Rgba16Float,
+        TrailFormat::R32Float => TextureFormat::R32Float,
+    }
+}
+
+// Storage texture formats are part of the shader source, so every shader touching the trail map picks its declarations from these
+fn get_shader_defs(app_config: &AppConfig) -> Vec<ShaderDefVal> {
+    match app_config.texture.trail_format {
+        TrailFormat::Rgba8Unorm => vec!["TRAIL_FORMAT_RGBA8UNORM".into()],
+        TrailFormat::Rgba16Float => vec!["TRAIL_FORMAT_RGBA16FLOAT".into()],
+        TrailFormat::R32Float => vec!["TRAIL_FORMAT_R32FLOAT".into(), "TRAIL_SINGLE_CHANNEL".into()],
+    }
+}
 
 #[derive(Default)]
 pub struct ShaderPipelineNode;
 
 impl Node for ShaderPipelineNode {
     fn run(&self, _graph: &mut RenderGraphContext, render_context: &mut RenderContext, world: &World) -> Result<(), NodeRunError> {
-        world.resource::<MainShaderPipeline>().run_shaders(render_context, world);
+        let pipeline = world.resource::<MainShaderPipeline>();
+
+        // Running the passes that happened to compile first would take steps that are never diffused or counted
+        if !pipeline.is_ready(world.resource::<PipelineCache>()) {
+            return Ok(());
+        }
+
+        pipeline.run_shaders(render_context, world);
 
         Ok(())
     }
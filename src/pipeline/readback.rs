@@ -0,0 +1,184 @@
+use std::num::NonZeroU32;
+
+use bevy::prelude::*;
+use bevy::render::{RenderApp, RenderSet};
+use bevy::render::main_graph::node::CAMERA_DRIVER;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext};
+use bevy::render::render_resource::*;
+use bevy::render::renderer::{RenderContext, RenderDevice};
+use wgpu::Maintain;
+
+use crate::AppConfig;
+use crate::pipeline::{MainShaderPipeline, PipelineImages};
+
+// Copies the display texture into a buffer the CPU can read, for screenshots, recordings and headless runs.
+// Each of them requests the copy for the frames it needs, so it is made at most once per frame
+pub struct ReadbackPlugin;
+
+impl Plugin for ReadbackPlugin {
+    fn build(&self, app: &mut App) {
+        let app_config = app.world.get_resource::<AppConfig>().cloned().unwrap();
+        let render_app = app.sub_app_mut(RenderApp);
+
+        let readback = ImageReadback::new(
+            render_app.world.resource::<RenderDevice>(),
+            app_config.texture.width,
+            app_config.texture.height,
+        );
+
+        render_app
+            .insert_resource(DisplayReadback {
+                readback,
+                is_requested: false,
+            })
+            .add_system(prepare_readback.in_set(RenderSet::Prepare));
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node(
+            "readback",
+            ReadbackNode,
+        );
+        render_graph.add_node_edge(
+            "simulation",
+            "readback",
+        );
+        render_graph.add_node_edge(
+            "readback",
+            CAMERA_DRIVER,
+        );
+    }
+}
+
+// Systems requesting the copy run in the prepare set after `prepare_readback`, and read it back in the cleanup set
+#[derive(Resource)]
+pub struct DisplayReadback {
+    pub readback: ImageReadback,
+    pub is_requested: bool,
+}
+
+pub fn prepare_readback(mut display_readback: ResMut<DisplayReadback>, app_config: Res<AppConfig>, render_device: Res<RenderDevice>) {
+    display_readback.is_requested = false;
+
+    if display_readback.readback.width() != app_config.texture.width || display_readback.readback.height() != app_config.texture.height {
+        display_readback.readback = ImageReadback::new(
+            render_device.as_ref(),
+            app_config.texture.width,
+            app_config.texture.height,
+        );
+    }
+}
+
+struct ReadbackNode;
+
+impl Node for ReadbackNode {
+    fn run(&self, _graph: &mut RenderGraphContext, render_context: &mut RenderContext, world: &World) -> Result<(), NodeRunError> {
+        let display_readback = world.resource::<DisplayReadback>();
+
+        // Nothing has been drawn to the display while the compute pipelines are still compiling
+        if !display_readback.is_requested || !world.resource::<MainShaderPipeline>().is_ready(world.resource::<PipelineCache>()) {
+            return Ok(());
+        }
+
+        let images = world.resource::<PipelineImages>();
+        let gpu_images = world.resource::<RenderAssets<Image>>();
+
+        display_readback.readback.copy_from(
+            render_context,
+            &gpu_images[&images.display].texture,
+        );
+
+        Ok(())
+    }
+}
+
+pub struct ImageReadback {
+    buffer: Buffer,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: usize,
+}
+
+impl ImageReadback {
+    pub fn new(render_device: &RenderDevice, width: u32, height: u32) -> Self {
+        let padded_bytes_per_row = RenderDevice::align_copy_bytes_per_row(width as usize * 4);
+
+        Self {
+            buffer: render_device
+                .create_buffer(
+                    &BufferDescriptor {
+                        label: Some("image readback buffer"),
+                        size: (padded_bytes_per_row * height as usize) as u64,
+                        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                        mapped_at_creation: false,
+                    },
+                ),
+            width,
+            height,
+            padded_bytes_per_row,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn copy_from(&self, render_context: &mut RenderContext, texture: &Texture) {
+        render_context.command_encoder().copy_texture_to_buffer(
+            texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(self.padded_bytes_per_row as u32),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    // Blocks until the copy recorded by `copy_from` has been submitted and executed
+    pub fn read(&self, render_device: &RenderDevice) -> Vec<u8> {
+        let slice = self.buffer.slice(..);
+        render_device.map_buffer(&slice, MapMode::Read, |result| {
+            result.expect("readback buffer to be mappable");
+        });
+        render_device.poll(Maintain::Wait);
+
+        let bytes = slice
+            .get_mapped_range()
+            .chunks_exact(self.padded_bytes_per_row)
+            .flat_map(|row| &row[..self.width as usize * 4])
+            .copied()
+            .collect();
+
+        self.buffer.unmap();
+        bytes
+    }
+
+    // The sprite is drawn over a black clear color, so bake that into the image instead of leaving it transparent
+    pub fn read_opaque(&self, render_device: &RenderDevice) -> Vec<u8> {
+        let mut bytes = self.read(render_device);
+
+        for pixel in bytes.chunks_exact_mut(4) {
+            let alpha = pixel[3] as u16;
+
+            for channel in &mut pixel[..3] {
+                *channel = (*channel as u16 * alpha / 255) as u8;
+            }
+
+            pixel[3] = 255;
+        }
+
+        bytes
+    }
+}
@@ -1,4 +1,3 @@
-use bevy::asset::Handle;
 use bevy::core::{Pod, Zeroable};
 use bevy::prelude::*;
 use bevy::render::render_asset::RenderAssets;
@@ -6,8 +5,8 @@ use bevy::render::render_resource::*;
 use bevy::render::renderer::{RenderDevice, RenderQueue};
 
 use crate::AppConfig;
-use crate::pipeline::{get_compute_pipeline_id, PipelineData, SubShaderPipeline};
-use crate::plugin::{PluginTime, SimulationSettings};
+use crate::pipeline::{DISPLAY_FORMAT, get_compute_pipeline_id, get_shader_defs, get_trail_format, PipelineData, PipelineImages, SubShaderPipeline};
+use crate::plugin::{BrushInput, MAX_GRADIENT_STOPS, MAX_SPECIES, PluginTime, SimulationSettings};
 
 pub struct RecolorShaderPipeline {
     bind_group_layout: BindGroupLayout,
@@ -20,7 +19,9 @@ impl RecolorShaderPipeline {
     pub fn new(world: &mut World) -> Self {
         let bind_group_layout = get_bind_group_layout(
             world.resource::<RenderDevice>(),
+            get_trail_format(world.resource::<AppConfig>()),
         );
+        let shader_defs = get_shader_defs(world.resource::<AppConfig>());
 
         let shader = world.resource::<AssetServer>().load("shaders/recolor.wgsl");
 
@@ -29,6 +30,7 @@ impl RecolorShaderPipeline {
                 shader,
                 world.resource_mut::<PipelineCache>().as_mut(),
                 bind_group_layout.clone(),
+                shader_defs,
                 "recolor shader update".to_string(),
                 "recolor".to_string(),
             ),
@@ -53,10 +55,8 @@ impl SubShaderPipeline for RecolorShaderPipeline {
         );
     }
 
-    fn prepare_data(&mut self, render_queue: &RenderQueue, _app_config: &AppConfig, settings: &SimulationSettings, _time: &PluginTime) {
-        self.context.data = Some(RecolorPipelineContext {
-            color: settings.color.as_rgba_f32(),
-        });
+    fn prepare_data(&mut self, _render_device: &RenderDevice, render_queue: &RenderQueue, _app_config: &AppConfig, settings: &SimulationSettings, _time: &PluginTime, _brush: &BrushInput) {
+        self.context.data = Some(RecolorPipelineContext::new(settings));
 
         render_queue.write_buffer(
             self.context.buffer.as_ref().expect("context buffer to exist"),
@@ -67,7 +67,7 @@ impl SubShaderPipeline for RecolorShaderPipeline {
         );
     }
 
-    fn queue_bind_groups(&mut self, render_device: &RenderDevice, gpu_images: &RenderAssets<Image>, images: &Vec<Handle<Image>>) {
+    fn queue_bind_groups(&mut self, render_device: &RenderDevice, gpu_images: &RenderAssets<Image>, images: &PipelineImages) {
         self.bind_group = Some(
             render_device.create_bind_group(
                 &BindGroupDescriptor {
@@ -77,16 +77,28 @@ impl SubShaderPipeline for RecolorShaderPipeline {
                         BindGroupEntry {
                             binding: 0,
                             resource: BindingResource::TextureView(
-                                &gpu_images[images.get(1).unwrap()].texture_view,
+                                &gpu_images[&images.trail[1]].texture_view,
                             ),
                         },
                         BindGroupEntry {
                             binding: 1,
+                            resource: BindingResource::TextureView(
+                                &gpu_images[&images.display].texture_view,
+                            ),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
                             resource: self.context.buffer
                                 .as_ref()
                                 .expect("context buffer to exist")
                                 .as_entire_binding(),
                         },
+                        BindGroupEntry {
+                            binding: 3,
+                            resource: BindingResource::TextureView(
+                                &gpu_images[&images.food].texture_view,
+                            ),
+                        },
                     ],
                 },
             )
@@ -100,9 +112,13 @@ impl SubShaderPipeline for RecolorShaderPipeline {
     fn get_bind_group(&self) -> Option<&BindGroup> {
         self.bind_group.as_ref()
     }
+
+    fn is_per_step(&self) -> bool {
+        false
+    }
 }
 
-fn get_bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
+fn get_bind_group_layout(render_device: &RenderDevice, trail_format: TextureFormat) -> BindGroupLayout {
     render_device
         .create_bind_group_layout(
             &BindGroupLayoutDescriptor {
@@ -112,8 +128,8 @@ fn get_bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
                         binding: 0,
                         visibility: ShaderStages::COMPUTE,
                         ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::ReadWrite,
-                            format: TextureFormat::Rgba8Unorm,
+                            access: StorageTextureAccess::ReadOnly,
+                            format: trail_format,
                             view_dimension: TextureViewDimension::D2,
                         },
                         count: None,
@@ -121,6 +137,16 @@ fn get_bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
                     BindGroupLayoutEntry {
                         binding: 1,
                         visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::StorageTexture {
+                            access: StorageTextureAccess::WriteOnly,
+                            format: DISPLAY_FORMAT,
+                            view_dimension: TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::COMPUTE,
                         ty: BindingType::Buffer {
                             ty: BufferBindingType::Uniform,
                             has_dynamic_offset: false,
@@ -128,6 +154,18 @@ fn get_bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
                         },
                         count: None,
                     },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float {
+                                filterable: false,
+                            },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
                 ],
             },
         )
@@ -135,6 +173,47 @@ fn get_bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
 
 #[repr(C)]
 #[derive(Copy, Clone, Default, Pod, Zeroable)]
-struct RecolorPipelineContext {
-    color: [f32; 4],
+pub(crate) struct RecolorPipelineContext {
+    pub colors: [[f32; 4]; MAX_SPECIES],
+    pub num_species: u32,
+    pub deposit_mode: u32,
+    pub num_stops: u32,
+    _padding: u32,
+    pub stops: [GradientStopContext; MAX_GRADIENT_STOPS],
+    pub food_color: [f32; 4],
+    pub show_food: u32,
+    _food_padding: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+pub(crate) struct GradientStopContext {
+    pub color: [f32; 4],
+    pub position: f32,
+    _padding: [f32; 3],
+}
+
+impl RecolorPipelineContext {
+    pub fn new(settings: &SimulationSettings) -> Self {
+        let gradient = settings.get_gradient();
+
+        Self {
+            colors: std::array::from_fn(|index| settings.get_species(index).color.as_rgba_f32()),
+            num_species: settings.num_species(),
+            deposit_mode: settings.deposit_mode as u32,
+            num_stops: gradient.len() as u32,
+            _padding: 0,
+            stops: std::array::from_fn(|index| gradient
+                .get(index)
+                .map(|stop| GradientStopContext {
+                    color: stop.color.as_rgba_f32(),
+                    position: stop.position,
+                    _padding: [0.0; 3],
+                })
+                .unwrap_or_default()),
+            food_color: settings.food_color.as_rgba_f32(),
+            show_food: if settings.show_food { 1 } else { 0 },
+            _food_padding: [0; 3],
+        }
+    }
 }
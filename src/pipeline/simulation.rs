@@ -1,39 +1,51 @@
-use std::f32::consts::PI;
-use std::num::NonZeroU32;
-
 use bevy::core::{Pod, Zeroable};
 use bevy::prelude::*;
 use bevy::render::render_asset::RenderAssets;
 use bevy::render::render_resource::*;
-use bevy::render::renderer::{RenderDevice, RenderQueue};
-use rand::{Rng, thread_rng};
+use bevy::render::renderer::{RenderContext, RenderDevice, RenderQueue};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::{AppConfig, TrailFormat};
+use crate::pipeline::{get_compute_pipeline_id, get_shader_defs, get_trail_format, PipelineData, PipelineImages, run_shader, SubShaderPipeline, WorkgroupSize};
+use crate::pipeline::spawn::{respawn_agents, spawn_agents};
+use crate::plugin::{BrushInput, BrushTool, DepositMode, MAX_SPECIES, PluginTime, SimulationSettings};
 
-use crate::AppConfig;
-use crate::pipeline::{get_compute_pipeline_id, PipelineData, SubShaderPipeline, WorkgroupSize};
-use crate::plugin::{PluginTime, SimulationSettings};
+const MAX_WORKGROUPS_PER_DIMENSION: u32 = 65535;
 
 pub struct SimulationShaderPipeline {
     bind_group_layout: BindGroupLayout,
     bind_group: Option<BindGroup>,
     pipeline: CachedComputePipelineId,
     agents: PipelineData<Vec<Agent>>,
-    context: PipelineData<SimulationPipelineContext>,
+    context: PipelineData<Vec<SimulationPipelineContext>>,
+    context_stride: u64,
+    species: PipelineData<SpeciesPipelineContext>,
+    rescale_bind_group_layout: BindGroupLayout,
+    rescale_bind_group: Option<BindGroup>,
+    rescale_pipeline: CachedComputePipelineId,
+    rescale: PipelineData<RescalePipelineContext>,
+    texture_size: UVec2,
 }
 
 impl SimulationShaderPipeline {
     pub fn new(world: &mut World) -> Self {
         let bind_group_layout = get_bind_group_layout(
             world.resource::<RenderDevice>(),
-            world.resource::<SimulationSettings>(),
+            get_trail_format(world.resource::<AppConfig>()),
         );
+        let shader_defs = get_shader_defs(world.resource::<AppConfig>());
+        let rescale_bind_group_layout = get_rescale_bind_group_layout(world.resource::<RenderDevice>());
 
         let shader = world.resource::<AssetServer>().load("shaders/simulation.wgsl");
+        let rescale_shader = world.resource::<AssetServer>().load("shaders/rescale.wgsl");
 
         Self {
             pipeline: get_compute_pipeline_id(
                 shader,
                 world.resource_mut::<PipelineCache>().as_mut(),
                 bind_group_layout.clone(),
+                shader_defs,
                 "simulation shader update".to_string(),
                 "update".to_string(),
             ),
@@ -41,40 +53,111 @@ impl SimulationShaderPipeline {
             bind_group: None,
             agents: PipelineData::default(),
             context: PipelineData::default(),
+            context_stride: 0,
+            species: PipelineData::default(),
+            rescale_pipeline: get_compute_pipeline_id(
+                rescale_shader,
+                world.resource_mut::<PipelineCache>().as_mut(),
+                rescale_bind_group_layout.clone(),
+                vec![],
+                "simulation shader rescale".to_string(),
+                "rescale".to_string(),
+            ),
+            rescale_bind_group_layout,
+            rescale_bind_group: None,
+            rescale: PipelineData::default(),
+            texture_size: UVec2::ZERO,
         }
     }
 }
 
+impl SimulationShaderPipeline {
+    // Returns how many agents were carried over from the previous buffer
+    fn resize_agents(
+        &mut self,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        app_config: &AppConfig,
+        settings: &SimulationSettings,
+        num_agents: usize,
+    ) -> usize {
+        let (agents, preserved) = respawn_agents(num_agents, app_config, settings);
+
+        let buffer = render_device
+            .create_buffer_with_data(
+                &BufferInitDescriptor {
+                    label: Some("agents storage buffer"),
+                    contents: bevy::core::cast_slice(&agents),
+                    usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+                }
+            );
+
+        // Surviving agents keep their state, only the newly added ones take their place from the spawn pattern
+        if preserved > 0 {
+            let mut command_encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("agents resize command encoder"),
+            });
+
+            command_encoder.copy_buffer_to_buffer(
+                self.agents.buffer.as_ref().expect("agents buffer to exist"),
+                0,
+                &buffer,
+                0,
+                (preserved * std::mem::size_of::<Agent>()) as u64,
+            );
+
+            render_queue.submit([command_encoder.finish()]);
+        }
+
+        self.agents.data = Some(agents);
+        self.agents.buffer = Some(buffer);
+
+        preserved
+    }
+}
+
 impl SubShaderPipeline for SimulationShaderPipeline {
     fn init_data(&mut self, render_device: &RenderDevice, app_config: &AppConfig, settings: &SimulationSettings) {
-        let mut rng = thread_rng();
+        // Every step dispatched in a frame reads its own context through a dynamic offset
+        let alignment = render_device.limits().min_uniform_buffer_offset_alignment as u64;
+        self.context_stride = (std::mem::size_of::<SimulationPipelineContext>() as u64).div_ceil(alignment) * alignment;
 
         self.context.buffer = Some(render_device
             .create_buffer(
                 &BufferDescriptor {
                     label: Some("simulation context uniform buffer"),
-                    size: std::mem::size_of::<SimulationPipelineContext>() as u64,
+                    size: self.context_stride * app_config.pipeline.max_steps_per_frame as u64,
                     usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
                     mapped_at_creation: false,
                 }
             )
         );
 
-        self.agents.data = Some((0..settings.num_agents)
-            .into_iter()
-            .map(|_| {
-                let r = rng.gen::<f32>().sqrt() * 600.0;
-                let theta = rng.gen::<f32>() * PI * 2.0;
-
-                Agent {
-                    position: [
-                        (app_config.texture.width as f32 / 2.0) + r * theta.cos(),
-                        (app_config.texture.height as f32 / 2.0) + r * theta.sin(),
-                    ],
-                    angle: theta + PI,
-                    _padding: 0,
+        self.species.buffer = Some(render_device
+            .create_buffer(
+                &BufferDescriptor {
+                    label: Some("species uniform buffer"),
+                    size: std::mem::size_of::<SpeciesPipelineContext>() as u64,
+                    usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
                 }
-            }).collect::<Vec<Agent>>());
+            )
+        );
+
+        self.rescale.buffer = Some(render_device
+            .create_buffer(
+                &BufferDescriptor {
+                    label: Some("rescale uniform buffer"),
+                    size: std::mem::size_of::<RescalePipelineContext>() as u64,
+                    usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }
+            )
+        );
+
+        self.texture_size = UVec2::new(app_config.texture.width, app_config.texture.height);
+
+        self.agents.data = Some(spawn_agents(app_config, settings, &mut StdRng::seed_from_u64(settings.seed)));
 
         self.agents.buffer = Some(render_device
             .create_buffer_with_data(
@@ -84,30 +167,65 @@ impl SubShaderPipeline for SimulationShaderPipeline {
                         &self.agents.data
                             .as_ref()
                             .expect("agents data to exist")),
-                    usage: BufferUsages::STORAGE,
+                    usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
                 }
             ));
     }
 
-    fn prepare_data(&mut self, render_queue: &RenderQueue, app_config: &AppConfig, settings: &SimulationSettings, time: &PluginTime) {
-        self.context.data = Some(SimulationPipelineContext {
-            pause: if settings.pause { 1 } else { 0 },
-            width: app_config.texture.width,
-            height: app_config.texture.height,
-            speed: settings.agent_speed,
-            delta_time: time.delta_time,
-            time: time.time,
-            sense_angle_offset: settings.agent_sense_angle_offset,
-            sense_distance: settings.agent_sense_distance,
-            turn_speed: settings.agent_turn_speed,
-            turn_randomness: settings.agent_turn_randomness,
+    fn prepare_data(&mut self, render_device: &RenderDevice, render_queue: &RenderQueue, app_config: &AppConfig, settings: &SimulationSettings, time: &PluginTime, brush: &BrushInput) {
+        self.context.data = Some((0..time.steps)
+            .map(|step| SimulationPipelineContext::new(app_config, settings, time, brush, step))
+            .collect());
+
+        let mut num_agents = self.agents.data.as_ref().expect("agents data to exist").len();
+
+        if num_agents != settings.num_agents as usize {
+            num_agents = self.resize_agents(render_device, render_queue, app_config, settings, num_agents);
+        }
+
+        // Agents are moved proportionally when the canvas is resized so they keep their place in the pattern,
+        // newly spawned agents already use the new size so only the ones carried over are rescaled
+        let texture_size = UVec2::new(app_config.texture.width, app_config.texture.height);
+
+        self.rescale.data = (texture_size != self.texture_size).then(|| RescalePipelineContext {
+            scale: (texture_size.as_vec2() / self.texture_size.as_vec2()).to_array(),
+            count: num_agents as u32,
+            _padding: 0,
         });
+        self.texture_size = texture_size;
+
+        if let Some(rescale) = self.rescale.data {
+            render_queue.write_buffer(
+                self.rescale.buffer.as_ref().expect("rescale buffer to exist"),
+                0,
+                bevy::core::cast_slice(&[rescale]),
+            );
+        }
+
+        let mut contents = vec![0; (self.context_stride * time.steps as u64) as usize];
+
+        for (slot, context) in contents
+            .chunks_exact_mut(self.context_stride as usize)
+            .zip(self.context.data.as_ref().expect("context data to exist"))
+        {
+            slot[..std::mem::size_of::<SimulationPipelineContext>()].copy_from_slice(bevy::core::bytes_of(context));
+        }
+
+        if !contents.is_empty() {
+            render_queue.write_buffer(
+                self.context.buffer.as_ref().expect("context buffer to exist"),
+                0,
+                &contents,
+            );
+        }
+
+        self.species.data = Some(SpeciesPipelineContext::new(settings));
 
         render_queue.write_buffer(
-            self.context.buffer.as_ref().expect("context buffer to exist"),
+            self.species.buffer.as_ref().expect("species buffer to exist"),
             0,
             bevy::core::cast_slice(&[
-                self.context.data.expect("context data to exist")
+                self.species.data.expect("species data to exist"),
             ]),
         );
     }
@@ -116,7 +234,7 @@ impl SubShaderPipeline for SimulationShaderPipeline {
         &mut self,
         render_device: &RenderDevice,
         gpu_images: &RenderAssets<Image>,
-        images: &Vec<Handle<Image>>,
+        images: &PipelineImages,
     ) {
         self.bind_group = Some(
             render_device.create_bind_group(
@@ -127,32 +245,78 @@ impl SubShaderPipeline for SimulationShaderPipeline {
                         BindGroupEntry {
                             binding: 0,
                             resource: BindingResource::TextureView(
-                                &gpu_images[images.get(1).unwrap()].texture_view,
+                                &gpu_images[&images.trail[1]].texture_view,
                             ),
                         },
                         BindGroupEntry {
                             binding: 1,
                             resource: BindingResource::TextureView(
-                                &gpu_images[images.get(0).unwrap()].texture_view,
+                                &gpu_images[&images.trail[0]].texture_view,
                             ),
                         },
                         BindGroupEntry {
                             binding: 2,
-                            resource: self.context.buffer
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: self.context.buffer
+                                    .as_ref()
+                                    .expect("context buffer to exist"),
+                                offset: 0,
+                                size: BufferSize::new(std::mem::size_of::<SimulationPipelineContext>() as u64),
+                            }),
+                        },
+                        BindGroupEntry {
+                            binding: 3,
+                            resource: self.agents.buffer
                                 .as_ref()
-                                .expect("context buffer to exist")
+                                .expect("agents buffer to exist")
                                 .as_entire_binding(),
                         },
                         BindGroupEntry {
-                            binding: 3,
+                            binding: 4,
+                            resource: self.species.buffer
+                                .as_ref()
+                                .expect("species buffer to exist")
+                                .as_entire_binding(),
+                        },
+                        BindGroupEntry {
+                            binding: 5,
+                            resource: BindingResource::TextureView(
+                                &gpu_images[&images.obstacles].texture_view,
+                            ),
+                        },
+                        BindGroupEntry {
+                            binding: 6,
+                            resource: BindingResource::TextureView(
+                                &gpu_images[&images.food].texture_view,
+                            ),
+                        },
+                    ],
+                },
+            ));
+
+        self.rescale_bind_group = Some(
+            render_device.create_bind_group(
+                &BindGroupDescriptor {
+                    label: Some("rescale bind group"),
+                    layout: &self.rescale_bind_group_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
                             resource: self.agents.buffer
                                 .as_ref()
                                 .expect("agents buffer to exist")
                                 .as_entire_binding(),
                         },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: self.rescale.buffer
+                                .as_ref()
+                                .expect("rescale buffer to exist")
+                                .as_entire_binding(),
+                        },
                     ],
                 },
-            ))
+            ));
     }
 
     fn get_pipeline(&self) -> CachedComputePipelineId {
@@ -163,16 +327,48 @@ impl SubShaderPipeline for SimulationShaderPipeline {
         self.bind_group.as_ref()
     }
 
-    fn get_workgroup_size(&self, _app_config: &AppConfig, settings: &SimulationSettings) -> WorkgroupSize {
-        WorkgroupSize {
-            x: settings.num_agents / 16,
-            y: 1,
-            z: 1,
+    fn get_dynamic_offsets(&self, step: u32) -> Vec<u32> {
+        vec![(self.context_stride * step as u64) as u32]
+    }
+
+    fn is_ready(&self, pipeline_cache: &PipelineCache) -> bool {
+        [self.pipeline, self.rescale_pipeline]
+            .into_iter()
+            .all(|pipeline| matches!(pipeline_cache.get_compute_pipeline_state(pipeline), CachedPipelineState::Ok(_)))
+    }
+
+    fn run_before_steps(&self, render_context: &mut RenderContext, world: &World) {
+        if self.rescale.data.is_none() {
+            return;
         }
+
+        run_shader(
+            render_context,
+            world.resource::<PipelineCache>(),
+            self.rescale_pipeline,
+            self.rescale_bind_group.as_ref(),
+            &[],
+            self.get_workgroup_size(world.resource::<AppConfig>(), world.resource::<SimulationSettings>()),
+        );
+    }
+
+    fn get_workgroup_size(&self, _app_config: &AppConfig, settings: &SimulationSettings) -> WorkgroupSize {
+        get_agent_workgroup_size(settings.num_agents)
     }
 }
 
-fn get_bind_group_layout(render_device: &RenderDevice, settings: &SimulationSettings) -> BindGroupLayout {
+// A dispatch fits at most 65535 workgroups along each axis, so larger agent counts continue on further rows
+fn get_agent_workgroup_size(num_agents: u32) -> WorkgroupSize {
+    let workgroups = num_agents.div_ceil(16);
+
+    WorkgroupSize {
+        x: workgroups.min(MAX_WORKGROUPS_PER_DIMENSION),
+        y: workgroups.div_ceil(MAX_WORKGROUPS_PER_DIMENSION),
+        z: 1,
+    }
+}
+
+fn get_bind_group_layout(render_device: &RenderDevice, trail_format: TextureFormat) -> BindGroupLayout {
     render_device
         .create_bind_group_layout(
             &BindGroupLayoutDescriptor {
@@ -183,7 +379,7 @@ fn get_bind_group_layout(render_device: &RenderDevice, settings: &SimulationSett
                         visibility: ShaderStages::COMPUTE,
                         ty: BindingType::StorageTexture {
                             access: StorageTextureAccess::ReadOnly,
-                            format: TextureFormat::Rgba8Unorm,
+                            format: trail_format,
                             view_dimension: TextureViewDimension::D2,
                         },
                         count: None,
@@ -193,7 +389,7 @@ fn get_bind_group_layout(render_device: &RenderDevice, settings: &SimulationSett
                         visibility: ShaderStages::COMPUTE,
                         ty: BindingType::StorageTexture {
                             access: StorageTextureAccess::WriteOnly,
-                            format: TextureFormat::Rgba8Unorm,
+                            format: trail_format,
                             view_dimension: TextureViewDimension::D2,
                         },
                         count: None,
@@ -203,7 +399,7 @@ fn get_bind_group_layout(render_device: &RenderDevice, settings: &SimulationSett
                         visibility: ShaderStages::COMPUTE,
                         ty: BindingType::Buffer {
                             ty: BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
+                            has_dynamic_offset: true,
                             min_binding_size: BufferSize::new(std::mem::size_of::<SimulationPipelineContext>() as u64),
                         },
                         count: None,
@@ -218,8 +414,74 @@ fn get_bind_group_layout(render_device: &RenderDevice, settings: &SimulationSett
                             has_dynamic_offset: false,
                             min_binding_size: BufferSize::new(std::mem::size_of::<Agent>() as u64),
                         },
-                        count: Some(NonZeroU32::try_from(settings.num_agents)
-                            .expect("more than zero agents")),
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(std::mem::size_of::<SpeciesPipelineContext>() as u64),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float {
+                                filterable: false,
+                            },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float {
+                                filterable: false,
+                            },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
+            },
+        )
+}
+
+fn get_rescale_bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
+    render_device
+        .create_bind_group_layout(
+            &BindGroupLayoutDescriptor {
+                label: Some("rescale bind group layout"),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage {
+                                read_only: false,
+                            },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(std::mem::size_of::<Agent>() as u64),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(std::mem::size_of::<RescalePipelineContext>() as u64),
+                        },
+                        count: None,
                     },
                 ],
             },
@@ -228,24 +490,130 @@ fn get_bind_group_layout(render_device: &RenderDevice, settings: &SimulationSett
 
 #[repr(C)]
 #[derive(Copy, Clone, Default, Pod, Zeroable)]
-struct SimulationPipelineContext {
-    pause: u32,
-    width: u32,
-    height: u32,
-    speed: f32,
-    delta_time: f32,
-    time: f32,
-    sense_angle_offset: f32,
-    sense_distance: f32,
-    turn_speed: f32,
-    turn_randomness: f32,
+pub(crate) struct RescalePipelineContext {
+    pub scale: [f32; 2],
+    pub count: u32,
+    _padding: u32,
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+pub(crate) struct SimulationPipelineContext {
+    pub pause: u32,
+    pub width: u32,
+    pub height: u32,
+    pub delta_time: f32,
+    pub tick: u32,
+    pub seed: u32,
+    pub deposit_mode: u32,
+    pub boundary_mode: u32,
+    pub brush: BrushContext,
+}
 
+impl SimulationPipelineContext {
+    pub fn new(app_config: &AppConfig, settings: &SimulationSettings, time: &PluginTime, brush: &BrushInput, step: u32) -> Self {
+        Self {
+            pause: if settings.pause { 1 } else { 0 },
+            width: app_config.texture.width,
+            height: app_config.texture.height,
+            delta_time: time.delta_time,
+            tick: time.tick + step,
+            seed: (settings.seed ^ (settings.seed >> 32)) as u32,
+            deposit_mode: settings.deposit_mode as u32,
+            boundary_mode: settings.boundary_mode as u32,
+            brush: BrushContext::new(app_config, settings, brush),
+        }
+    }
+}
+
+// Shared by the simulation and the diffuse pass, `paint` is the value the paint and erase tools blend the trail map towards
 #[repr(C)]
 #[derive(Copy, Clone, Default, Pod, Zeroable)]
-pub struct Agent {
-    position: [f32; 2],
-    angle: f32,
+pub(crate) struct BrushContext {
+    pub paint: [f32; 4],
+    pub position: [f32; 2],
+    pub is_active: u32,
+    pub tool: u32,
+    pub size: f32,
+    pub strength: f32,
+    pub delta_time: f32,
     _padding: u32,
 }
+
+impl BrushContext {
+    pub fn new(app_config: &AppConfig, settings: &SimulationSettings, brush: &BrushInput) -> Self {
+        let species = settings.brush_species.min(settings.num_species() - 1);
+
+        let paint = match (settings.brush_tool, settings.deposit_mode) {
+            (BrushTool::Erase, _) => Vec4::ZERO,
+            _ if app_config.texture.trail_format == TrailFormat::R32Float => Vec4::ONE,
+            (_, DepositMode::Species) => Vec4::AXES[species as usize],
+            _ => Vec4::from(settings.get_species(species as usize).color.as_rgba_f32()).truncate().extend(1.0),
+        };
+
+        Self {
+            paint: paint.to_array(),
+            position: brush.position.to_array(),
+            is_active: if brush.active { 1 } else { 0 },
+            tool: settings.brush_tool as u32,
+            size: settings.brush_size,
+            strength: settings.brush_strength,
+            delta_time: brush.delta_time,
+            _padding: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+pub(crate) struct SpeciesContext {
+    pub speed: f32,
+    pub sense_angle_offset: f32,
+    pub sense_distance: f32,
+    pub turn_speed: f32,
+    pub turn_randomness: f32,
+    pub sensor_size: u32,
+    pub sensor_count: u32,
+    pub sensor_weighting: u32,
+    pub color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+pub(crate) struct SpeciesPipelineContext {
+    pub species: [SpeciesContext; MAX_SPECIES],
+    pub num_species: u32,
+    _padding: [u32; 3],
+}
+
+impl SpeciesPipelineContext {
+    pub fn new(settings: &SimulationSettings) -> Self {
+        Self {
+            species: std::array::from_fn(|index| {
+                let species = settings.get_species(index);
+
+                SpeciesContext {
+                    speed: species.agent_speed,
+                    sense_angle_offset: species.agent_sense_angle_offset,
+                    sense_distance: species.agent_sense_distance,
+                    turn_speed: species.agent_turn_speed,
+                    turn_randomness: species.agent_turn_randomness,
+                    sensor_size: species.agent_sensor_size,
+                    sensor_count: species.agent_sensor_count,
+                    sensor_weighting: species.agent_sensor_weighting as u32,
+                    color: species.color.as_rgba_f32(),
+                }
+            }),
+            num_species: settings.num_species(),
+            _padding: [0; 3],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Pod, Zeroable)]
+pub struct Agent {
+    pub position: [f32; 2],
+    pub angle: f32,
+    pub species: u32,
+}
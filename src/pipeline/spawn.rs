@@ -0,0 +1,241 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use rand::Rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::AppConfig;
+use crate::pipeline::simulation::Agent;
+use crate::plugin::{AgentCountChange, SimulationSettings, SpawnFacing, SpawnMode};
+
+pub fn spawn_agents(app_config: &AppConfig, settings: &SimulationSettings, rng: &mut impl Rng) -> Vec<Agent> {
+    let size = Vec2::new(app_config.texture.width as f32, app_config.texture.height as f32);
+    let center = size / 2.0;
+
+    let mask = match &settings.spawn_mode {
+        SpawnMode::Image { path, threshold } => load_mask(path, *threshold, size),
+        _ => None,
+    };
+
+    (0..settings.num_agents)
+        .map(|_| {
+            let (position, angle) = match &settings.spawn_mode {
+                SpawnMode::Random => (
+                    Vec2::new(rng.gen::<f32>(), rng.gen::<f32>()) * size,
+                    rng.gen::<f32>() * PI * 2.0,
+                ),
+                SpawnMode::Point { offset } => (
+                    center + *offset,
+                    rng.gen::<f32>() * PI * 2.0,
+                ),
+                SpawnMode::Disc { radius, facing } => {
+                    let r = rng.gen::<f32>().sqrt() * radius;
+                    let theta = rng.gen::<f32>() * PI * 2.0;
+
+                    (center + Vec2::from_angle(theta) * r, get_angle(*facing, theta, rng))
+                },
+                SpawnMode::Ring { radius, thickness, facing } => {
+                    let inner = (radius - thickness / 2.0).max(0.0);
+                    let outer = radius + thickness / 2.0;
+
+                    // Sampling the squared radius keeps the agents evenly spread over the area of the ring
+                    let r = (inner * inner + rng.gen::<f32>() * (outer * outer - inner * inner)).sqrt();
+                    let theta = rng.gen::<f32>() * PI * 2.0;
+
+                    (center + Vec2::from_angle(theta) * r, get_angle(*facing, theta, rng))
+                },
+                SpawnMode::Rectangle { size: rectangle_size, facing } => {
+                    let position = center + get_rectangle_edge_point(*rectangle_size, rng.gen::<f32>());
+                    let theta = (position - center).y.atan2((position - center).x);
+
+                    (position, get_angle(*facing, theta, rng))
+                },
+                SpawnMode::Image { .. } => match &mask {
+                    Some(mask) => (
+                        (mask.texels[rng.gen_range(0..mask.texels.len())] + Vec2::new(rng.gen::<f32>(), rng.gen::<f32>())) * mask.texel_size,
+                        rng.gen::<f32>() * PI * 2.0,
+                    ),
+                    None => (
+                        Vec2::new(rng.gen::<f32>(), rng.gen::<f32>()) * size,
+                        rng.gen::<f32>() * PI * 2.0,
+                    ),
+                },
+            };
+
+            let species = rng.gen_range(0..settings.num_species());
+
+            Agent {
+                position: position.clamp(Vec2::ZERO, size - 1.0).to_array(),
+                angle,
+                species,
+            }
+        }).collect::<Vec<Agent>>()
+}
+
+// Spawns the full list for the new count and returns how many of the current agents keep their state
+pub fn respawn_agents(num_agents: usize, app_config: &AppConfig, settings: &SimulationSettings) -> (Vec<Agent>, usize) {
+    let spawned = spawn_agents(app_config, settings, &mut StdRng::seed_from_u64(settings.seed));
+
+    let preserved = match settings.on_agent_count_change {
+        AgentCountChange::Preserve => num_agents.min(spawned.len()),
+        AgentCountChange::Respawn => 0,
+    };
+
+    (spawned, preserved)
+}
+
+pub fn resize_agents(agents: &mut Vec<Agent>, app_config: &AppConfig, settings: &SimulationSettings) {
+    let (spawned, preserved) = respawn_agents(agents.len(), app_config, settings);
+
+    agents.truncate(preserved);
+    agents.extend_from_slice(&spawned[preserved..]);
+}
+
+// `theta` is the direction from the centre of the spawn shape to the agent
+fn get_angle(facing: SpawnFacing, theta: f32, rng: &mut impl Rng) -> f32 {
+    match facing {
+        SpawnFacing::Inward => theta + PI,
+        SpawnFacing::Outward => theta,
+        SpawnFacing::Random => rng.gen::<f32>() * PI * 2.0,
+    }
+}
+
+fn get_rectangle_edge_point(size: Vec2, t: f32) -> Vec2 {
+    let half_size = size.abs() / 2.0;
+    let mut distance = t * (size.x.abs() + size.y.abs()) * 2.0;
+
+    for (start, edge) in [
+        (Vec2::new(-half_size.x, -half_size.y), Vec2::new(size.x.abs(), 0.0)),
+        (Vec2::new(half_size.x, -half_size.y), Vec2::new(0.0, size.y.abs())),
+        (Vec2::new(half_size.x, half_size.y), Vec2::new(-size.x.abs(), 0.0)),
+        (Vec2::new(-half_size.x, half_size.y), Vec2::new(0.0, -size.y.abs())),
+    ] {
+        let length = edge.length();
+
+        if distance <= length && length > 0.0 {
+            return start + edge * (distance / length);
+        }
+
+        distance -= length;
+    }
+
+    -half_size
+}
+
+struct SpawnMask {
+    texels: Vec<Vec2>,
+    texel_size: Vec2,
+}
+
+fn load_mask(path: &str, threshold: f32, size: Vec2) -> Option<SpawnMask> {
+    let image = match image::open(path) {
+        Ok(image) => image.into_luma8(),
+        Err(err) => {
+            error!("Failed to load spawn mask {}, spawning randomly instead: {}", path, err);
+            return None;
+        },
+    };
+
+    let texels = image
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel.0[0] as f32 / 255.0 > threshold)
+        .map(|(x, y, _)| Vec2::new(x as f32, y as f32))
+        .collect::<Vec<Vec2>>();
+
+    if texels.is_empty() {
+        warn!("Spawn mask {} has no pixels brighter than {}, spawning randomly instead", path, threshold);
+        return None;
+    }
+
+    Some(SpawnMask {
+        texels,
+        texel_size: size / Vec2::new(image.width() as f32, image.height() as f32),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn_test_agents(spawn_mode: SpawnMode) -> Vec<Agent> {
+        let mut app_config = AppConfig::default();
+        app_config.texture.width = 400;
+        app_config.texture.height = 300;
+
+        let settings = SimulationSettings {
+            num_agents: 1000,
+            spawn_mode,
+            ..default()
+        };
+
+        spawn_agents(&app_config, &settings, &mut StdRng::seed_from_u64(1))
+    }
+
+    fn get_offsets(agents: &[Agent]) -> Vec<Vec2> {
+        agents.iter().map(|agent| Vec2::from(agent.position) - Vec2::new(200.0, 150.0)).collect()
+    }
+
+    #[test]
+    fn spawn_shapes() {
+        for offset in get_offsets(&spawn_test_agents(SpawnMode::Point { offset: Vec2::new(10.0, -20.0) })) {
+            assert_eq!(offset, Vec2::new(10.0, -20.0));
+        }
+
+        for offset in get_offsets(&spawn_test_agents(SpawnMode::Disc { radius: 100.0, facing: SpawnFacing::Inward })) {
+            assert!(offset.length() <= 100.0 + 1e-3, "{} is outside the disc", offset);
+        }
+
+        for offset in get_offsets(&spawn_test_agents(SpawnMode::Ring { radius: 100.0, thickness: 20.0, facing: SpawnFacing::Inward })) {
+            assert!((90.0 - 1e-3..=110.0 + 1e-3).contains(&offset.length()), "{} is outside the ring", offset);
+        }
+
+        for offset in get_offsets(&spawn_test_agents(SpawnMode::Rectangle { size: Vec2::new(200.0, 100.0), facing: SpawnFacing::Inward })) {
+            let distance_to_edge = (offset.x.abs() - 100.0).abs().min((offset.y.abs() - 50.0).abs());
+            assert!(offset.x.abs() <= 100.0 + 1e-3 && offset.y.abs() <= 50.0 + 1e-3 && distance_to_edge < 1e-3, "{} is not on the rectangle", offset);
+        }
+    }
+
+    #[test]
+    fn spawn_facing() {
+        for agent in spawn_test_agents(SpawnMode::Disc { radius: 100.0, facing: SpawnFacing::Outward }) {
+            let offset = Vec2::from(agent.position) - Vec2::new(200.0, 150.0);
+            assert!(Vec2::from_angle(agent.angle).dot(offset.normalize_or_zero()) > 0.999 || offset.length() < 1e-3);
+        }
+
+        for agent in spawn_test_agents(SpawnMode::Disc { radius: 100.0, facing: SpawnFacing::Inward }) {
+            let offset = Vec2::from(agent.position) - Vec2::new(200.0, 150.0);
+            assert!(Vec2::from_angle(agent.angle).dot(offset.normalize_or_zero()) < -0.999 || offset.length() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn resize_preserves_or_respawns() {
+        let mut app_config = AppConfig::default();
+        app_config.texture.width = 400;
+        app_config.texture.height = 300;
+
+        let mut settings = SimulationSettings {
+            num_agents: 100,
+            on_agent_count_change: AgentCountChange::Preserve,
+            ..default()
+        };
+
+        let mut agents = spawn_agents(&app_config, &settings, &mut StdRng::seed_from_u64(settings.seed));
+        for agent in &mut agents {
+            agent.angle = 42.0;
+        }
+
+        settings.num_agents = 150;
+        resize_agents(&mut agents, &app_config, &settings);
+        assert_eq!(agents.len(), 150);
+        assert!(agents[..100].iter().all(|agent| agent.angle == 42.0));
+        assert!(agents[100..].iter().all(|agent| agent.angle != 42.0));
+
+        settings.num_agents = 50;
+        settings.on_agent_count_change = AgentCountChange::Respawn;
+        resize_agents(&mut agents, &app_config, &settings);
+        assert_eq!(agents.len(), 50);
+        assert!(agents.iter().all(|agent| agent.angle != 42.0));
+    }
+}
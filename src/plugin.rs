@@ -1,16 +1,23 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use bevy::prelude::*;
 use bevy::render::{RenderApp, RenderSet};
 use bevy::render::extract_resource::{ExtractResource, ExtractResourcePlugin};
 use bevy::render::main_graph::node::CAMERA_DRIVER;
 use bevy::render::render_asset::RenderAssets;
 use bevy::render::render_graph::RenderGraph;
-use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages};
+use bevy::render::render_resource::{Extent3d, PipelineCache, TextureDimension, TextureFormat, TextureUsages};
 use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::window::PrimaryWindow;
+use bevy_inspector_egui::bevy_egui::EguiContext;
 use bevy_inspector_egui::prelude::*;
 use bevy_inspector_egui::quick::ResourceInspectorPlugin;
+use serde::{Deserialize, Serialize};
 
 use crate::AppConfig;
-use crate::pipeline::{MainShaderPipeline, PipelineImages, ShaderPipelineNode};
+use crate::pipeline::maps::{get_food_map, load_obstacle_map};
+use crate::pipeline::{DISPLAY_FORMAT, FOOD_FORMAT, get_trail_format, MainShaderPipeline, OBSTACLE_FORMAT, PipelineImages, ShaderPipelineNode};
 
 pub struct SlimeSimulationPlugin;
 
@@ -19,21 +26,59 @@ impl Plugin for SlimeSimulationPlugin {
         app
             .init_resource::<SimulationSettings>()
             .register_type::<SimulationSettings>()
-            .add_plugin(ResourceInspectorPlugin::<SimulationSettings>::default())
+            .register_type::<SpeciesSettings>()
+            .register_type::<SpawnMode>()
+            .register_type::<SpawnFacing>()
+            .register_type::<AgentCountChange>()
+            .register_type::<DepositMode>()
+            .register_type::<ColorMap>()
+            .register_type::<GradientStop>()
+            .register_type::<DecayMode>()
+            .register_type::<DiffuseKernel>()
+            .register_type::<BoundaryMode>()
+            .register_type::<SensorWeighting>()
+            .register_type::<FoodSource>()
+            .register_type::<BrushTool>()
+            .init_resource::<PluginTime>()
+            .init_resource::<BrushInput>()
+            .init_resource::<PipelinesReady>()
+            .add_plugin(ResourceInspectorPlugin::<SimulationSettings>::default()
+                .run_if(any_with_component::<PrimaryWindow>()))
+            .add_plugin(ExtractResourcePlugin::<AppConfig>::default())
             .add_plugin(ExtractResourcePlugin::<SimulationSettings>::default())
             .add_plugin(ExtractResourcePlugin::<PipelineImages>::default())
             .add_plugin(ExtractResourcePlugin::<PluginTime>::default())
-            .add_startup_system(create_images);
+            .add_plugin(ExtractResourcePlugin::<BrushInput>::default())
+            .add_startup_system(create_images)
+            .add_system(resize_images.in_base_set(CoreSet::PostUpdate))
+            .add_system(update_obstacles.in_base_set(CoreSet::PostUpdate))
+            .add_system(update_food.in_base_set(CoreSet::PostUpdate))
+            .add_system(update_plugin_time)
+            .add_system(update_brush_input);
 
         let app_config = app.world.get_resource::<AppConfig>().cloned().unwrap();
+        let settings = app.world.get_resource::<SimulationSettings>().cloned().unwrap();
+        let pipelines_ready = app.world.resource::<PipelinesReady>().clone();
         let render_app = app.sub_app_mut(RenderApp);
 
+        let limits = render_app.world.resource::<RenderDevice>().limits();
+        if let Err(err) = app_config
+            .validate_texture_size(limits.max_texture_dimension_2d)
+            .and_then(|_| app_config.validate_agent_count(limits.max_compute_workgroups_per_dimension, limits.max_storage_buffer_binding_size))
+        {
+            error!("Invalid config: {}", err);
+            std::process::exit(1);
+        }
+
+        // The pipelines spawn their agents while being built, before the settings are first extracted
         render_app
             .insert_resource(app_config)
-            .init_resource::<SimulationSettings>()
+            .insert_resource(settings)
+            .insert_resource(pipelines_ready)
             .init_resource::<MainShaderPipeline>()
             .add_system(queue_bind_groups.in_set(RenderSet::Queue))
-            .add_system(prepare_data.in_set(RenderSet::Prepare));
+            .add_system(prepare_data.in_set(RenderSet::Prepare))
+            .add_system(update_pipelines_ready.in_set(RenderSet::Cleanup));
 
         let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
         render_graph.add_node(
@@ -48,28 +93,223 @@ impl Plugin for SlimeSimulationPlugin {
 }
 
 fn create_images(mut commands: Commands, app_config: Res<AppConfig>, mut images: ResMut<Assets<Image>>) {
-    let mut pipeline_images: Vec<Handle<Image>> = Vec::new();
-
-    for _ in 0..2 {
-        let mut image = Image::new_fill(
-            Extent3d {
-                width: app_config.texture.width,
-                height: app_config.texture.height,
-                depth_or_array_layers: 1,
-            },
-            TextureDimension::D2,
-            &[0, 0, 0, 0],
-            TextureFormat::Rgba8Unorm,
-        );
+    let mut obstacles = create_image(app_config.as_ref(), OBSTACLE_FORMAT);
+    obstacles.texture_descriptor.usage = TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING;
+
+    let mut food = create_image(app_config.as_ref(), FOOD_FORMAT);
+    food.texture_descriptor.usage = TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING;
 
-        image.texture_descriptor.usage = TextureUsages::COPY_DST
-            | TextureUsages::STORAGE_BINDING
-            | TextureUsages::TEXTURE_BINDING;
+    commands.insert_resource(PipelineImages {
+        trail: [
+            images.add(create_image(app_config.as_ref(), get_trail_format(app_config.as_ref()))),
+            images.add(create_image(app_config.as_ref(), get_trail_format(app_config.as_ref()))),
+        ],
+        display: images.add(create_image(app_config.as_ref(), DISPLAY_FORMAT)),
+        obstacles: images.add(obstacles),
+        food: images.add(food),
+    });
+}
+
+fn create_image(app_config: &AppConfig, format: TextureFormat) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: app_config.texture.width,
+            height: app_config.texture.height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &vec![0; format.describe().block_size as usize],
+        format,
+    );
+
+    image.texture_descriptor.usage = TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC
+        | TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING;
+
+    image
+}
 
-        pipeline_images.push(images.add(image));
+fn resize_images(app_config: Res<AppConfig>, pipeline_images: Res<PipelineImages>, mut images: ResMut<Assets<Image>>) {
+    if !app_config.is_changed() {
+        return;
     }
 
-    commands.insert_resource(PipelineImages(pipeline_images));
+    let size = Extent3d {
+        width: app_config.texture.width,
+        height: app_config.texture.height,
+        depth_or_array_layers: 1,
+    };
+
+    for handle in pipeline_images.trail.iter().chain([&pipeline_images.display]) {
+        if images.get(handle).is_some_and(|image| image.texture_descriptor.size != size) {
+            images.get_mut(handle).expect("pipeline image to exist").resize(size);
+        }
+    }
+}
+
+// Reloads the obstacle map when its path or the canvas size changes
+fn update_obstacles(
+    app_config: Res<AppConfig>,
+    settings: Res<SimulationSettings>,
+    pipeline_images: Res<PipelineImages>,
+    mut images: ResMut<Assets<Image>>,
+    mut loaded: Local<Option<(String, u32, u32)>>,
+) {
+    let (width, height) = (app_config.texture.width, app_config.texture.height);
+
+    if loaded.as_ref().is_some_and(|(path, loaded_width, loaded_height)| {
+        *path == settings.obstacle_map && *loaded_width == width && *loaded_height == height
+    }) {
+        return;
+    }
+
+    let image = images.get_mut(&pipeline_images.obstacles).expect("obstacle image to exist");
+    image.texture_descriptor.size = Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    image.data = load_obstacle_map(&settings.obstacle_map, width, height);
+
+    *loaded = Some((settings.obstacle_map.clone(), width, height));
+}
+
+// Rebuilds the food map when any of the food settings or the canvas size change
+fn update_food(
+    app_config: Res<AppConfig>,
+    settings: Res<SimulationSettings>,
+    pipeline_images: Res<PipelineImages>,
+    mut images: ResMut<Assets<Image>>,
+    mut loaded: Local<Option<(FoodSettings, u32, u32)>>,
+) {
+    let (width, height) = (app_config.texture.width, app_config.texture.height);
+    let food_settings = FoodSettings::new(settings.as_ref());
+
+    if loaded.as_ref() == Some(&(food_settings.clone(), width, height)) {
+        return;
+    }
+
+    let image = images.get_mut(&pipeline_images.food).expect("food image to exist");
+    image.texture_descriptor.size = Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    image.data = bevy::core::cast_slice(&get_food_map(settings.as_ref(), width, height)).to_vec();
+
+    *loaded = Some((food_settings, width, height));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_plugin_time(
+    time: Res<Time>,
+    app_config: Res<AppConfig>,
+    settings: Res<SimulationSettings>,
+    fixed_delta_time: Option<Res<FixedDeltaTime>>,
+    fixed_frame_time: Option<Res<FixedFrameTime>>,
+    tick_limit: Option<Res<TickLimit>>,
+    pipelines_ready: Res<PipelinesReady>,
+    mut accumulator: Local<f32>,
+    mut plugin_time: ResMut<PluginTime>,
+) {
+    plugin_time.tick += plugin_time.steps;
+
+    // No steps are taken before every pipeline has compiled, so the first tick and with it the
+    // random stream of a seed doesn't depend on how long compiling took
+    if settings.pause || !pipelines_ready.0.load(Ordering::Acquire) {
+        plugin_time.steps = 0;
+        *accumulator = 0.0;
+        return;
+    }
+
+    match fixed_delta_time {
+        Some(fixed_delta_time) => {
+            plugin_time.delta_time = fixed_delta_time.0;
+            plugin_time.steps = 1;
+        },
+        None => {
+            let max_steps = app_config.pipeline.max_steps_per_frame;
+
+            plugin_time.delta_time = 1.0 / app_config.pipeline.ticks_per_second as f32;
+            // Recordings advance the same amount of simulation time every frame, however long rendering it took
+            *accumulator += match fixed_frame_time {
+                Some(fixed_frame_time) => fixed_frame_time.0,
+                None => time.delta_seconds(),
+            };
+
+            plugin_time.steps = ((*accumulator / plugin_time.delta_time) as u32).min(max_steps);
+            *accumulator -= plugin_time.steps as f32 * plugin_time.delta_time;
+
+            // Drop the backlog after a hitch instead of trying to catch up over the following frames
+            if plugin_time.steps == max_steps {
+                *accumulator = accumulator.min(plugin_time.delta_time);
+            }
+        },
+    }
+
+    if let Some(tick_limit) = tick_limit {
+        plugin_time.steps = plugin_time.steps.min(tick_limit.0.saturating_sub(plugin_time.tick));
+    }
+}
+
+// Maps the cursor through the camera and the sprite showing the display image onto the canvas,
+// the brush is only active while the left mouse button is held outside of the inspector
+#[allow(clippy::too_many_arguments)]
+fn update_brush_input(
+    mouse_buttons: Res<Input<MouseButton>>,
+    app_config: Res<AppConfig>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    sprites: Query<(&Sprite, &GlobalTransform)>,
+    mut egui_contexts: Query<&mut EguiContext, With<PrimaryWindow>>,
+    time: Res<Time>,
+    mut brush: ResMut<BrushInput>,
+) {
+    brush.active = false;
+    brush.delta_time = time.delta_seconds();
+
+    let (Ok(window), Ok((camera, camera_transform)), Ok((sprite, sprite_transform))) = (windows.get_single(), cameras.get_single(), sprites.get_single()) else {
+        return;
+    };
+
+    if !mouse_buttons.pressed(MouseButton::Left) {
+        return;
+    }
+
+    if is_inspector_using_pointer(&mut egui_contexts) {
+        return;
+    }
+
+    let Some(position) = window.cursor_position().and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor)) else {
+        return;
+    };
+
+    let texture_size = Vec2::new(app_config.texture.width as f32, app_config.texture.height as f32);
+    let sprite_size = sprite.custom_size.unwrap_or(texture_size);
+
+    // The first row of the canvas is at the top of the sprite
+    let local_position = sprite_transform.affine().inverse().transform_point3(position.extend(0.0)).truncate();
+    let uv = local_position / sprite_size + 0.5;
+
+    brush.active = true;
+    brush.position = Vec2::new(uv.x, 1.0 - uv.y) * texture_size;
+}
+
+pub fn is_inspector_using_pointer(egui_contexts: &mut Query<&mut EguiContext, With<PrimaryWindow>>) -> bool {
+    egui_contexts.get_single_mut().is_ok_and(|mut egui_context| {
+        let egui_context = egui_context.get_mut();
+        egui_context.wants_pointer_input() || egui_context.is_pointer_over_area()
+    })
+}
+
+// True while a text field of the inspector has focus, so typing doesn't trigger any hotkeys
+pub fn is_inspector_using_keyboard(egui_contexts: &mut Query<&mut EguiContext, With<PrimaryWindow>>) -> bool {
+    egui_contexts.get_single_mut().is_ok_and(|mut egui_context| egui_context.get_mut().wants_keyboard_input())
+}
+
+fn update_pipelines_ready(pipeline: Res<MainShaderPipeline>, pipeline_cache: Res<PipelineCache>, pipelines_ready: Res<PipelinesReady>) {
+    pipelines_ready.0.store(pipeline.is_ready(pipeline_cache.as_ref()), Ordering::Release);
 }
 
 fn queue_bind_groups(
@@ -83,19 +323,366 @@ fn queue_bind_groups(
 
 fn prepare_data(
     mut pipeline: ResMut<MainShaderPipeline>,
+    render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
     app_config: Res<AppConfig>,
     settings: Res<SimulationSettings>,
     time: Res<PluginTime>,
+    brush: Res<BrushInput>,
 ) {
-    pipeline.prepare_data(render_queue.as_ref(), app_config.as_ref(), settings.as_ref(), time.as_ref());
+    pipeline.prepare_data(render_device.as_ref(), render_queue.as_ref(), app_config.as_ref(), settings.as_ref(), time.as_ref(), brush.as_ref());
 }
 
-#[derive(Clone, ExtractResource, InspectorOptions, Reflect, Resource)]
+#[derive(Clone, ExtractResource, InspectorOptions, Reflect, Resource, Serialize, Deserialize)]
 #[reflect(InspectorOptions, Resource)]
+#[serde(default, deny_unknown_fields)]
 pub struct SimulationSettings {
     pub pause: bool,
+    pub seed: u64,
+    // The most agents that fit the default storage buffer binding size of 128 MiB
+    #[inspector(min = 1, max = 8388608)]
     pub num_agents: u32,
+    pub on_agent_count_change: AgentCountChange,
+    pub spawn_mode: SpawnMode,
+    pub boundary_mode: BoundaryMode,
+    pub obstacle_map: String,
+    pub food_sources: Vec<FoodSource>,
+    pub food_map: String,
+    #[inspector(min = 0.0, max = 10.0, speed = 0.05)]
+    pub food_map_strength: f32,
+    pub show_food: bool,
+    #[serde(with = "color_serde")]
+    pub food_color: Color,
+    pub brush_tool: BrushTool,
+    #[inspector(min = 1.0, max = 500.0)]
+    pub brush_size: f32,
+    #[inspector(min = 0.0, max = 10.0, speed = 0.05)]
+    pub brush_strength: f32,
+    #[inspector(min = 0, max = 3)]
+    pub brush_species: u32,
+    pub species: Vec<SpeciesSettings>,
+    pub deposit_mode: DepositMode,
+    pub color_map: ColorMap,
+    pub gradient: Vec<GradientStop>,
+    pub has_trails: bool,
+    pub decay_mode: DecayMode,
+    #[inspector(min = 0.0, max = 5.0, speed = 0.005)]
+    pub decay_rate: f32,
+    pub diffuse_kernel: DiffuseKernel,
+    #[inspector(min = 0, max = 32)]
+    pub diffuse_radius: u32,
+    #[inspector(min = 0.0, max = 200.0, speed = 0.1)]
+    pub diffuse_rate: f32,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            pause: true,
+            seed: 0,
+            num_agents: 1000000,
+            on_agent_count_change: AgentCountChange::default(),
+            spawn_mode: SpawnMode::default(),
+            boundary_mode: BoundaryMode::default(),
+            obstacle_map: String::new(),
+            food_sources: Vec::new(),
+            food_map: String::new(),
+            food_map_strength: 1.0,
+            show_food: true,
+            food_color: Color::rgb(1.0, 0.85, 0.2),
+            brush_tool: BrushTool::default(),
+            brush_size: 40.0,
+            brush_strength: 1.0,
+            brush_species: 0,
+            species: vec![
+                SpeciesSettings {
+                    color: Color::rgb(1.0, 0.25, 0.35),
+                    ..default()
+                },
+                SpeciesSettings {
+                    agent_speed: 1.2,
+                    agent_sense_angle_offset: 0.7,
+                    agent_turn_speed: 1.5,
+                    color: Color::rgb(0.25, 1.0, 0.45),
+                    ..default()
+                },
+                SpeciesSettings {
+                    agent_speed: 0.8,
+                    agent_sense_distance: 12.0,
+                    agent_turn_speed: 0.7,
+                    color: Color::rgb(0.3, 0.5, 1.0),
+                    ..default()
+                },
+            ],
+            deposit_mode: DepositMode::default(),
+            color_map: ColorMap::default(),
+            gradient: vec![
+                GradientStop {
+                    position: 0.0,
+                    color: Color::BLACK,
+                },
+                GradientStop {
+                    position: 0.5,
+                    color: Color::rgb(0.9, 0.2, 0.5),
+                },
+                GradientStop {
+                    position: 1.0,
+                    color: Color::WHITE,
+                },
+            ],
+            has_trails: true,
+            decay_mode: DecayMode::default(),
+            decay_rate: 0.15,
+            diffuse_kernel: DiffuseKernel::default(),
+            diffuse_radius: 1,
+            diffuse_rate: 20.0,
+        }
+    }
+}
+
+impl SimulationSettings {
+    pub fn num_species(&self) -> u32 {
+        self.species.len().clamp(1, MAX_SPECIES) as u32
+    }
+
+    pub fn get_species(&self, index: usize) -> SpeciesSettings {
+        self.species.get(index).cloned().unwrap_or_default()
+    }
+
+    // Sorted by position and limited to MAX_GRADIENT_STOPS, empty when no colour map is used
+    pub fn get_gradient(&self) -> Vec<GradientStop> {
+        let mut gradient = match self.color_map {
+            ColorMap::Off => Vec::new(),
+            ColorMap::Gradient => self.gradient.clone(),
+            ColorMap::Viridis => get_palette(&[0x440154, 0x46337f, 0x365c8d, 0x277f8e, 0x1fa187, 0x4ac16d, 0x9fda3a, 0xfde725]),
+            ColorMap::Magma => get_palette(&[0x000004, 0x1d1147, 0x51127c, 0x832681, 0xb73779, 0xe75263, 0xfc8961, 0xfcfdbf]),
+            ColorMap::Inferno => get_palette(&[0x000004, 0x1f0c48, 0x550f6d, 0x88226a, 0xba3655, 0xe35933, 0xf98e09, 0xfcffa4]),
+            ColorMap::Slime => get_palette(&[0x000000, 0x06220a, 0x0b4d14, 0x17801f, 0x2fb82c, 0x6fe04a, 0xb8f57e, 0xeeffc2]),
+        };
+
+        // Stops past the limit, which only the inspector can add, are dropped from the end of the gradient rather than the end of the list
+        gradient.sort_by(|a, b| a.position.total_cmp(&b.position));
+        gradient.truncate(MAX_GRADIENT_STOPS);
+        gradient
+    }
+}
+
+// What happens to the existing agents when `num_agents` is changed while the simulation is running
+#[derive(Clone, Copy, Default, Eq, FromReflect, PartialEq, Reflect, Serialize, Deserialize)]
+#[reflect(Default)]
+pub enum AgentCountChange {
+    #[default]
+    Preserve,
+    Respawn,
+}
+
+// Distances are in texels, measured from the centre of the canvas
+#[derive(Clone, FromReflect, PartialEq, Reflect, Serialize, Deserialize)]
+#[serde(tag = "mode")]
+pub enum SpawnMode {
+    Random,
+    Point {
+        #[serde(default)]
+        offset: Vec2,
+    },
+    Disc {
+        #[serde(default = "get_default_spawn_radius")]
+        radius: f32,
+        #[serde(default)]
+        facing: SpawnFacing,
+    },
+    Ring {
+        #[serde(default = "get_default_spawn_radius")]
+        radius: f32,
+        #[serde(default = "get_default_ring_thickness")]
+        thickness: f32,
+        #[serde(default)]
+        facing: SpawnFacing,
+    },
+    Rectangle {
+        #[serde(default = "get_default_rectangle_size")]
+        size: Vec2,
+        #[serde(default)]
+        facing: SpawnFacing,
+    },
+    // Agents spawn on the pixels of the image brighter than the threshold, stretched over the canvas
+    Image {
+        path: String,
+        #[serde(default = "get_default_image_threshold")]
+        threshold: f32,
+    },
+}
+
+// Fields left out when switching modes, e.g. with `--set simulation.spawn_mode.mode=Ring`, take these values
+fn get_default_spawn_radius() -> f32 {
+    600.0
+}
+
+fn get_default_ring_thickness() -> f32 {
+    50.0
+}
+
+fn get_default_rectangle_size() -> Vec2 {
+    Vec2::new(1200.0, 800.0)
+}
+
+fn get_default_image_threshold() -> f32 {
+    0.5
+}
+
+impl Default for SpawnMode {
+    fn default() -> Self {
+        Self::Disc {
+            radius: get_default_spawn_radius(),
+            facing: SpawnFacing::Inward,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Eq, FromReflect, PartialEq, Reflect, Serialize, Deserialize)]
+#[reflect(Default)]
+pub enum SpawnFacing {
+    #[default]
+    Inward,
+    Outward,
+    Random,
+}
+
+// In the species mode every species has its own channel of the trail map and the display pass colours them,
+// the other modes deposit a colour directly and keep the trail intensity in the alpha channel
+#[derive(Clone, Copy, Default, Eq, FromReflect, PartialEq, Reflect, Serialize, Deserialize)]
+#[reflect(Default)]
+pub enum DepositMode {
+    #[default]
+    Species = 0,
+    Colour = 1,
+    Heading = 2,
+}
+
+// Maps the trail intensity through a gradient instead of the species colours or the deposited colour
+#[derive(Clone, Copy, Default, Eq, FromReflect, PartialEq, Reflect, Serialize, Deserialize)]
+#[reflect(Default)]
+pub enum ColorMap {
+    #[default]
+    Off,
+    Gradient,
+    Viridis,
+    Magma,
+    Inferno,
+    Slime,
+}
+
+pub const MAX_GRADIENT_STOPS: usize = 8;
+
+#[derive(Clone, FromReflect, InspectorOptions, Reflect, Serialize, Deserialize)]
+#[reflect(InspectorOptions)]
+#[serde(deny_unknown_fields)]
+pub struct GradientStop {
+    #[inspector(min = 0.0, max = 1.0, speed = 0.01)]
+    pub position: f32,
+    #[serde(with = "color_serde")]
+    pub color: Color,
+}
+
+// Evenly spaced stops from 0 to 1
+fn get_palette(colors: &[u32]) -> Vec<GradientStop> {
+    colors
+        .iter()
+        .enumerate()
+        .map(|(index, color)| GradientStop {
+            position: index as f32 / (colors.len() - 1) as f32,
+            color: Color::rgb_u8((color >> 16) as u8, (color >> 8) as u8, *color as u8),
+        })
+        .collect()
+}
+
+// Clamp keeps agents on the canvas and turns them in a random direction, wrap makes the canvas toroidal
+// and bounce reflects them off the edges. The sensors and the diffusion follow the same rule
+#[derive(Clone, Copy, Default, Eq, FromReflect, PartialEq, Reflect, Serialize, Deserialize)]
+#[reflect(Default)]
+pub enum BoundaryMode {
+    #[default]
+    Clamp = 0,
+    Wrap = 1,
+    Bounce = 2,
+}
+
+#[derive(Clone, Copy, Default, Eq, FromReflect, PartialEq, Reflect, Serialize, Deserialize)]
+#[reflect(Default)]
+pub enum DecayMode {
+    #[default]
+    Subtractive = 0,
+    Multiplicative = 1,
+}
+
+// The kernel is applied separably, first horizontally and then vertically
+#[derive(Clone, Copy, Default, Eq, FromReflect, PartialEq, Reflect, Serialize, Deserialize)]
+#[reflect(Default)]
+pub enum DiffuseKernel {
+    #[default]
+    Box = 0,
+    Gaussian = 1,
+}
+
+// Food is sensed by every species on top of the trail map. The position is relative to the centre of the canvas
+#[derive(Clone, FromReflect, InspectorOptions, PartialEq, Reflect, Serialize, Deserialize)]
+#[reflect(InspectorOptions)]
+#[serde(default, deny_unknown_fields)]
+pub struct FoodSource {
+    pub position: Vec2,
+    #[inspector(min = 1.0)]
+    pub radius: f32,
+    #[inspector(min = 0.0, max = 10.0, speed = 0.05)]
+    pub strength: f32,
+}
+
+impl Default for FoodSource {
+    fn default() -> Self {
+        Self {
+            position: Vec2::ZERO,
+            radius: 50.0,
+            strength: 1.0,
+        }
+    }
+}
+
+// Everything the food map is built from, used to tell when it needs rebuilding
+#[derive(Clone, PartialEq)]
+pub struct FoodSettings {
+    sources: Vec<FoodSource>,
+    map: String,
+    map_strength: f32,
+}
+
+impl FoodSettings {
+    pub fn new(settings: &SimulationSettings) -> Self {
+        Self {
+            sources: settings.food_sources.clone(),
+            map: settings.food_map.clone(),
+            map_strength: settings.food_map_strength,
+        }
+    }
+}
+
+// Paint and erase work on the trail map, attract and repel turn the agents under the brush.
+// Painting deposits the trail of `brush_species` in the species deposit mode
+#[derive(Clone, Copy, Default, Eq, FromReflect, PartialEq, Reflect, Serialize, Deserialize)]
+#[reflect(Default)]
+pub enum BrushTool {
+    #[default]
+    Paint = 0,
+    Erase = 1,
+    Attract = 2,
+    Repel = 3,
+}
+
+// Each species leaves its trail in its own channel of the RGBA trail map
+pub const MAX_SPECIES: usize = 4;
+
+#[derive(Clone, FromReflect, InspectorOptions, Reflect, Serialize, Deserialize)]
+#[reflect(InspectorOptions)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpeciesSettings {
     #[inspector(min = 0.1, max = 5.0)]
     pub agent_speed: f32,
     #[inspector(min = 0.0, max = 2.0, speed = 0.05)]
@@ -103,47 +690,83 @@ pub struct SimulationSettings {
     #[inspector(min = 0.0, max = 30.0)]
     pub agent_sense_distance: f32,
     pub agent_turn_speed: f32,
-    #[inspector(min = 0.0, max = 2.0, speed = 0.05)]
+    #[inspector(min = 0.0, max = 1.0, speed = 0.05)]
     pub agent_turn_randomness: f32,
+    #[inspector(min = 0, max = 5)]
+    pub agent_sensor_size: u32,
+    #[inspector(min = 1, max = 16)]
+    pub agent_sensor_count: u32,
+    pub agent_sensor_weighting: SensorWeighting,
+    #[serde(with = "color_serde")]
     pub color: Color,
-    pub has_trails: bool,
-    #[inspector(min = 0.0, max = 5.0, speed = 0.005)]
-    pub fade_rate: f32,
-    #[inspector(min = 0, max = 7)]
-    pub blur_radius: u32,
 }
 
-impl Default for SimulationSettings {
+impl Default for SpeciesSettings {
     fn default() -> Self {
         Self {
-            pause: true,
-            num_agents: 1000000,
             agent_speed: 1.0,
             agent_sense_angle_offset: 0.5,
             agent_sense_distance: 20.0,
             agent_turn_speed: 1.0,
             agent_turn_randomness: 0.1,
+            agent_sensor_size: 1,
+            agent_sensor_count: 3,
+            agent_sensor_weighting: SensorWeighting::default(),
             color: Color::WHITE,
-            has_trails: true,
-            fade_rate: 0.15,
-            blur_radius: 1,
         }
     }
 }
 
-#[derive(Resource)]
-pub struct PluginTime {
-    pub delta_time: f32,
-    pub time: f32,
+// Strongest turns towards the sensor with the highest reading, proportional steers towards
+// the average sensor direction weighted by the readings
+#[derive(Clone, Copy, Default, Eq, FromReflect, PartialEq, Reflect, Serialize, Deserialize)]
+#[reflect(Default)]
+pub enum SensorWeighting {
+    #[default]
+    Strongest = 0,
+    Proportional = 1,
 }
 
-impl ExtractResource for PluginTime {
-    type Source = Time;
+// Colours are stored as plain RGBA arrays, TOML has no way to represent the variants of Color
+mod color_serde {
+    use bevy::prelude::Color;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-    fn extract_resource(source: &Self::Source) -> Self {
-        Self {
-            delta_time: source.delta_seconds_f64() as f32,
-            time: source.elapsed_seconds_f64() as f32,
-        }
+    pub fn serialize<S: Serializer>(color: &Color, serializer: S) -> Result<S::Ok, S::Error> {
+        color.as_rgba_f32().serialize(serializer)
     }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        <[f32; 4]>::deserialize(deserializer).map(Color::from)
+    }
+}
+
+#[derive(Clone, Default, ExtractResource, Resource)]
+pub struct PluginTime {
+    pub delta_time: f32,
+    pub tick: u32,
+    pub steps: u32,
 }
+
+// The position is in texels of the canvas
+#[derive(Clone, Default, ExtractResource, Resource)]
+pub struct BrushInput {
+    pub active: bool,
+    pub position: Vec2,
+    // Painting happens once per frame, so it keeps working while paused
+    pub delta_time: f32,
+}
+
+// Shared between the main and the render world, set by the render world once every pipeline has compiled
+#[derive(Clone, Default, Resource)]
+pub struct PipelinesReady(Arc<AtomicBool>);
+
+#[derive(Resource)]
+pub struct FixedDeltaTime(pub f32);
+
+#[derive(Resource)]
+pub struct FixedFrameTime(pub f32);
+
+// No more steps are taken once the tick reaches the limit
+#[derive(Resource)]
+pub struct TickLimit(pub u32);
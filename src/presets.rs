@@ -0,0 +1,218 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy::render::renderer::RenderDevice;
+use bevy::render::settings::WgpuLimits;
+use bevy::window::PrimaryWindow;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+
+use crate::{AppConfig, ConfigSource};
+use crate::plugin::{is_inspector_using_keyboard, SimulationSettings};
+
+const PRESETS_DIRECTORY: &str = "presets";
+
+// Written to the presets directory the first time it is created
+const BUNDLED_PRESETS: &[(&str, &str)] = &[
+    ("01_tricolour", include_str!("../assets/presets/01_tricolour.toml")),
+    ("02_veins", include_str!("../assets/presets/02_veins.toml")),
+    ("03_magma_rings", include_str!("../assets/presets/03_magma_rings.toml")),
+    ("04_rainbow_swarm", include_str!("../assets/presets/04_rainbow_swarm.toml")),
+    ("05_foraging", include_str!("../assets/presets/05_foraging.toml")),
+];
+
+// F5 saves the current settings as a new preset, F9 reloads the current preset
+// and page up and page down switch to the previous and next one
+pub struct PresetsPlugin;
+
+impl Plugin for PresetsPlugin {
+    fn build(&self, app: &mut App) {
+        let source = app.world.resource::<ConfigSource>();
+        let directory = get_presets_directory(&source.path);
+
+        // Switching presets continues from the one given with `--preset`
+        let current = source.preset.clone();
+
+        app
+            .insert_resource(Presets {
+                directory,
+                current,
+            })
+            .add_system(handle_preset_keys);
+    }
+}
+
+#[derive(Resource)]
+pub struct Presets {
+    directory: PathBuf,
+    current: Option<String>,
+}
+
+// The presets directory sits next to the config file
+pub fn get_presets_directory(config_path: &Path) -> PathBuf {
+    config_path
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(PRESETS_DIRECTORY)
+}
+
+// Presets are looked up by their file name without the extension, anything else is taken as a path.
+// The bundled presets can be used by name before they have been written to the presets directory
+pub fn load_preset(directory: &Path, name: &str) -> Result<SimulationSettings, String> {
+    let path = get_preset_path(directory, name);
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => BUNDLED_PRESETS
+            .iter()
+            .find(|(bundled_name, _)| *bundled_name == name)
+            .map(|(_, contents)| contents.to_string())
+            .ok_or_else(|| format!("Failed to read preset {}: {}", path.display(), err))?,
+        Err(err) => return Err(format!("Failed to read preset {}: {}", path.display(), err)),
+    };
+
+    toml::from_str(contents.as_str())
+        .map_err(|err| format!("Failed to parse preset {}: {}", path.display(), err))
+}
+
+fn save_preset(directory: &Path, name: &str, settings: &SimulationSettings) -> Result<(), String> {
+    let path = get_preset_path(directory, name);
+
+    let contents = toml::to_string(settings)
+        .map_err(|err| format!("Failed to serialize preset {}: {}", name, err))?;
+
+    fs::write(&path, contents)
+        .map_err(|err| format!("Failed to write preset {}: {}", path.display(), err))
+}
+
+fn get_preset_path(directory: &Path, name: &str) -> PathBuf {
+    if name.ends_with(".toml") {
+        PathBuf::from(name)
+    } else {
+        directory.join(format!("{}.toml", name))
+    }
+}
+
+// Sorted by name, the bundled presets are written out first if there is no presets directory yet
+fn get_preset_names(directory: &Path) -> Vec<String> {
+    if fs::metadata(directory).is_err() {
+        let written = fs::create_dir_all(directory).and_then(|_| {
+            BUNDLED_PRESETS
+                .iter()
+                .try_for_each(|(name, contents)| fs::write(get_preset_path(directory, name), contents))
+        });
+
+        if let Err(err) = written {
+            error!("Failed to write the bundled presets to {}: {}", directory.display(), err);
+        }
+    }
+
+    let mut names = fs::read_dir(directory)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+                .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+
+    names.sort();
+    names
+}
+
+// The preset has to make a valid config together with the rest of the current one, like the config file on startup
+fn validate_preset(app_config: &AppConfig, preset: &SimulationSettings, limits: &WgpuLimits) -> Result<(), String> {
+    let mut config = app_config.clone();
+    config.simulation = preset.clone();
+
+    let mut errors = config.validate();
+    if let Err(err) = config.validate_agent_count(limits.max_compute_workgroups_per_dimension, limits.max_storage_buffer_binding_size) {
+        errors.push(err);
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n  "))
+    }
+}
+
+fn handle_preset_keys(
+    keys: Res<Input<KeyCode>>,
+    mut egui_contexts: Query<&mut EguiContext, With<PrimaryWindow>>,
+    app_config: Res<AppConfig>,
+    render_device: Res<RenderDevice>,
+    mut presets: ResMut<Presets>,
+    mut settings: ResMut<SimulationSettings>,
+) {
+    if is_inspector_using_keyboard(&mut egui_contexts) {
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::F5) {
+        let names = get_preset_names(&presets.directory);
+        let name = (1..)
+            .map(|index| format!("preset_{}", index))
+            .find(|name| !names.contains(name))
+            .expect("an unused preset name to exist");
+
+        match save_preset(&presets.directory, &name, settings.as_ref()) {
+            Ok(()) => {
+                info!("Saved preset {}", name);
+                presets.current = Some(name);
+            },
+            Err(err) => error!("{}", err),
+        }
+
+        return;
+    }
+
+    let offset = if keys.just_pressed(KeyCode::PageDown) {
+        1
+    } else if keys.just_pressed(KeyCode::PageUp) {
+        -1
+    } else if keys.just_pressed(KeyCode::F9) {
+        0
+    } else {
+        return;
+    };
+
+    // The directory is listed again every time, so presets added while running show up
+    let names = get_preset_names(&presets.directory);
+
+    if names.is_empty() {
+        warn!("There are no presets in {}", presets.directory.display());
+        return;
+    }
+
+    let index = match presets.current.as_ref().and_then(|current| names.iter().position(|name| name == current)) {
+        Some(index) => (index as i32 + offset).rem_euclid(names.len() as i32) as usize,
+        None if offset < 0 => names.len() - 1,
+        None => 0,
+    };
+
+    match load_preset(&presets.directory, &names[index]).and_then(|preset| {
+        validate_preset(app_config.as_ref(), &preset, &render_device.limits())
+            .map(|_| preset)
+            .map_err(|err| format!("Invalid preset {}:\n  {}\nKeeping the current settings", names[index], err))
+    }) {
+        Ok(preset) => {
+            info!("Loaded preset {}", names[index]);
+
+            if preset.spawn_mode != settings.spawn_mode {
+                info!("The spawn pattern of preset {} only applies to agents spawned from now on", names[index]);
+            }
+
+            // Loading a preset never pauses or unpauses the simulation
+            let pause = settings.pause;
+            *settings = preset;
+            settings.pause = pause;
+        },
+        Err(err) => error!("{}", err),
+    }
+
+    presets.current = Some(names[index].clone());
+}
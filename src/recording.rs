@@ -0,0 +1,345 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::render::{RenderApp, RenderSet};
+use bevy::render::extract_resource::{ExtractResource, ExtractResourcePlugin};
+use bevy::render::render_resource::PipelineCache;
+use bevy::render::renderer::RenderDevice;
+use bevy::window::PrimaryWindow;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+
+use crate::{AppConfig, ConfigSource, RecordingFormat};
+use crate::pipeline::MainShaderPipeline;
+use crate::pipeline::readback::{DisplayReadback, prepare_readback};
+use crate::plugin::{FixedFrameTime, is_inspector_using_keyboard, PluginTime};
+
+// F12 saves the current frame as a PNG and F10 starts and stops recording every frame,
+// either as numbered PNGs or piped into an external encoder
+pub struct RecordingPlugin {
+    pub record: bool,
+}
+
+impl Plugin for RecordingPlugin {
+    fn build(&self, app: &mut App) {
+        let app_config = app.world.get_resource::<AppConfig>().cloned().unwrap();
+        let directory = CaptureDirectory(get_capture_directory(
+            &app.world.resource::<ConfigSource>().path,
+            &app_config.recording.directory,
+        ));
+
+        let recorder = Recorder::default();
+
+        app
+            .init_resource::<CaptureRequest>()
+            .insert_resource(recorder.clone())
+            .add_plugin(ExtractResourcePlugin::<CaptureRequest>::default())
+            .add_system(handle_capture_keys)
+            .add_system(finish_recording_on_exit.in_base_set(CoreSet::Last));
+
+        if self.record {
+            start_recording(&mut app.world.resource_mut::<CaptureRequest>(), &app_config, &directory.0);
+            app.insert_resource(FixedFrameTime(1.0 / app_config.recording.fps as f32));
+        }
+
+        app.insert_resource(directory);
+
+        app
+            .sub_app_mut(RenderApp)
+            .insert_resource(recorder)
+            .add_system(request_capture.in_set(RenderSet::Prepare).after(prepare_readback))
+            .add_system(save_capture.in_set(RenderSet::Cleanup));
+    }
+}
+
+// The screenshot is only set for the frame it was requested in, the recording for as long as it runs
+#[derive(Clone, Default, ExtractResource, Resource)]
+pub struct CaptureRequest {
+    screenshot: Option<PathBuf>,
+    recording: Option<Recording>,
+}
+
+#[derive(Resource)]
+struct CaptureDirectory(PathBuf);
+
+#[derive(Clone)]
+struct Recording {
+    output: PathBuf,
+    format: RecordingFormat,
+    command: String,
+    fps: u32,
+    width: u32,
+    height: u32,
+}
+
+// Shared between the main and the render world, so the main world can still finish the recording when the app exits
+#[derive(Clone, Default, Resource)]
+struct Recorder(Arc<Mutex<RecorderState>>);
+
+#[derive(Default)]
+struct RecorderState {
+    recording: Option<ActiveRecording>,
+    is_exiting: bool,
+}
+
+struct ActiveRecording {
+    output: PathBuf,
+    frames: u32,
+    // Missing when the recording failed to start or to write a frame, the rest of its frames are dropped
+    sink: Option<FrameSink>,
+}
+
+enum FrameSink {
+    Images(PathBuf),
+    Encoder(Child),
+}
+
+// Dropping a recording closes the input of its encoder and waits for it to finish writing the video
+impl Drop for ActiveRecording {
+    fn drop(&mut self) {
+        if let Some(FrameSink::Encoder(encoder)) = &mut self.sink {
+            drop(encoder.stdin.take());
+
+            match encoder.wait() {
+                Ok(status) if !status.success() => error!("The encoder of {} exited with {}", self.output.display(), status),
+                Err(err) => error!("Failed to wait for the encoder of {}: {}", self.output.display(), err),
+                _ => {},
+            }
+        }
+
+        info!("Stopped recording {} after {} frames", self.output.display(), self.frames);
+    }
+}
+
+fn handle_capture_keys(
+    keys: Res<Input<KeyCode>>,
+    mut egui_contexts: Query<&mut EguiContext, With<PrimaryWindow>>,
+    app_config: Res<AppConfig>,
+    directory: Res<CaptureDirectory>,
+    mut request: ResMut<CaptureRequest>,
+    mut commands: Commands,
+) {
+    if request.screenshot.is_some() {
+        request.screenshot = None;
+    }
+
+    // Raw frames piped into an encoder must all have the same size
+    if request.recording.as_ref().is_some_and(|recording| recording.width != app_config.texture.width || recording.height != app_config.texture.height) {
+        warn!("Stopped recording because the canvas was resized");
+        stop_recording(&mut request, &mut commands);
+    }
+
+    if is_inspector_using_keyboard(&mut egui_contexts) {
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::F12) {
+        let path = get_unused_path(&directory.0, "capture").with_extension("png");
+        request.screenshot = Some(path);
+    }
+
+    if keys.just_pressed(KeyCode::F10) {
+        if request.recording.is_some() {
+            stop_recording(&mut request, &mut commands);
+        } else {
+            start_recording(&mut request, app_config.as_ref(), &directory.0);
+            commands.insert_resource(FixedFrameTime(1.0 / app_config.recording.fps as f32));
+        }
+    }
+}
+
+fn start_recording(request: &mut CaptureRequest, app_config: &AppConfig, directory: &Path) {
+    let recording = Recording {
+        output: get_unused_path(directory, "recording"),
+        format: app_config.recording.format,
+        command: app_config.recording.command.clone(),
+        fps: app_config.recording.fps,
+        width: app_config.texture.width,
+        height: app_config.texture.height,
+    };
+
+    info!("Started recording {} at {} frames per second", recording.output.display(), recording.fps);
+
+    if recording.fps * app_config.pipeline.max_steps_per_frame < app_config.pipeline.ticks_per_second {
+        warn!(
+            "Recording at {} frames per second needs more than max_steps_per_frame = {} steps per frame, so the recording will run slower than the simulation",
+            recording.fps, app_config.pipeline.max_steps_per_frame,
+        );
+    }
+
+    request.recording = Some(recording);
+}
+
+fn stop_recording(request: &mut CaptureRequest, commands: &mut Commands) {
+    request.recording = None;
+    commands.remove_resource::<FixedFrameTime>();
+}
+
+// Relative directories are taken from the directory of the config file, like the presets
+fn get_capture_directory(config_path: &Path, directory: &Path) -> PathBuf {
+    config_path
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(directory)
+}
+
+// Numbered after the first index no file in the directory uses yet, ignoring the extension
+// so an encoder is free to add its own
+fn get_unused_path(directory: &Path, prefix: &str) -> PathBuf {
+    let names = fs::read_dir(directory)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.path().file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+
+    let name = (1..)
+        .map(|index| format!("{}_{}", prefix, index))
+        .find(|name| !names.contains(name))
+        .expect("an unused capture name to exist");
+
+    directory.join(name)
+}
+
+// The render world may not run again after the app was told to exit, so the recording is finished here
+fn finish_recording_on_exit(mut exit_events: EventReader<AppExit>, recorder: Res<Recorder>) {
+    if exit_events.iter().last().is_none() {
+        return;
+    }
+
+    let mut recorder = recorder.0.lock().expect("recorder lock to not be poisoned");
+    recorder.is_exiting = true;
+    recorder.recording = None;
+}
+
+fn request_capture(request: Res<CaptureRequest>, mut display_readback: ResMut<DisplayReadback>) {
+    if request.screenshot.is_some() || request.recording.is_some() {
+        display_readback.is_requested = true;
+    }
+}
+
+fn save_capture(
+    request: Res<CaptureRequest>,
+    display_readback: Res<DisplayReadback>,
+    recorder: Res<Recorder>,
+    time: Res<PluginTime>,
+    render_device: Res<RenderDevice>,
+    main_pipeline: Res<MainShaderPipeline>,
+    pipeline_cache: Res<PipelineCache>,
+) {
+    let mut recorder = recorder.0.lock().expect("recorder lock to not be poisoned");
+
+    if recorder.is_exiting {
+        return;
+    }
+
+    let is_recording = |active: &ActiveRecording| request.recording.as_ref().is_some_and(|recording| recording.output == active.output);
+
+    if recorder.recording.as_ref().is_some_and(|active| !is_recording(active)) {
+        recorder.recording = None;
+    }
+
+    // Frames in which the simulation didn't advance, e.g. while paused, are left out of recordings
+    let recording = request.recording.as_ref().filter(|_| time.steps > 0);
+
+    // Nothing was copied into the readback buffer while the compute pipelines are still compiling
+    if (request.screenshot.is_none() && recording.is_none()) || !main_pipeline.is_ready(pipeline_cache.as_ref()) {
+        return;
+    }
+
+    let bytes = display_readback.readback.read_opaque(render_device.as_ref());
+    let (width, height) = (display_readback.readback.width(), display_readback.readback.height());
+
+    if let Some(path) = &request.screenshot {
+        match save_png(path, &bytes, width, height) {
+            Ok(()) => info!("Saved {}", path.display()),
+            Err(err) => error!("{}", err),
+        }
+    }
+
+    let Some(recording) = recording else {
+        return;
+    };
+
+    let active = recorder.recording.get_or_insert_with(|| ActiveRecording {
+        output: recording.output.clone(),
+        frames: 0,
+        sink: begin_recording(recording)
+            .map_err(|err| error!("{}", err))
+            .ok(),
+    });
+
+    let written = match &mut active.sink {
+        Some(FrameSink::Images(directory)) => save_png(&directory.join(format!("frame_{:06}.png", active.frames)), &bytes, width, height),
+        Some(FrameSink::Encoder(encoder)) => encoder.stdin
+            .as_mut()
+            .expect("encoder stdin to be piped")
+            .write_all(&bytes)
+            .map_err(|err| format!("Failed to write frame {} to the encoder of {}: {}", active.frames, active.output.display(), err)),
+        None => return,
+    };
+
+    match written {
+        Ok(()) => active.frames += 1,
+        Err(err) => {
+            error!("{}, dropping the rest of the recording", err);
+            active.sink = None;
+        },
+    }
+}
+
+fn begin_recording(recording: &Recording) -> Result<FrameSink, String> {
+    match recording.format {
+        RecordingFormat::Png => {
+            fs::create_dir_all(&recording.output)
+                .map_err(|err| format!("Failed to create {}: {}", recording.output.display(), err))?;
+
+            Ok(FrameSink::Images(recording.output.clone()))
+        },
+        RecordingFormat::Pipe => {
+            if let Some(directory) = recording.output.parent() {
+                fs::create_dir_all(directory)
+                    .map_err(|err| format!("Failed to create {}: {}", directory.display(), err))?;
+            }
+
+            // Split before filling in the placeholders, so paths with spaces stay a single argument
+            let arguments = recording.command
+                .split_whitespace()
+                .map(|argument| {
+                    argument
+                        .replace("{width}", &recording.width.to_string())
+                        .replace("{height}", &recording.height.to_string())
+                        .replace("{fps}", &recording.fps.to_string())
+                        .replace("{output}", &recording.output.to_string_lossy())
+                })
+                .collect::<Vec<String>>();
+
+            let (program, arguments) = arguments
+                .split_first()
+                .ok_or_else(|| "Failed to start the encoder: recording.command is empty".to_string())?;
+
+            Command::new(program)
+                .args(arguments)
+                .stdin(Stdio::piped())
+                .spawn()
+                .map(FrameSink::Encoder)
+                .map_err(|err| format!("Failed to start the encoder {}: {}", program, err))
+        },
+    }
+}
+
+fn save_png(path: &Path, bytes: &[u8], width: u32, height: u32) -> Result<(), String> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)
+            .map_err(|err| format!("Failed to create {}: {}", directory.display(), err))?;
+    }
+
+    image::save_buffer(path, bytes, width, height, image::ColorType::Rgba8)
+        .map_err(|err| format!("Failed to save {}: {}", path.display(), err))
+}